@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The crates whose types cross the plugin boundary, i.e. `flume::Sender` in `Plugin::update`.
+/// A plugin built against other versions of them lays those types out differently.
+const SHARED_CRATES: &[&str] = &["flume", "serde", "serde_json"];
+
+fn main() {
+    // Plugins are only compatible with renovatio if they were built by the same compiler,
+    // so record its version for the plugin loader to compare.
+    println!(
+        "cargo:rustc-env=RENOVATIO_RUSTC_VERSION={}",
+        rustc_version().unwrap_or_else(|| {
+            println!("cargo:warning=Couldn't run rustc --version, plugins may not load");
+            "unknown".to_string()
+        })
+    );
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-env-changed=RUSTC_WRAPPER");
+
+    // ...and against the same versions of the crates they share with it
+    let dependencies = match find_lockfile() {
+        Some(lockfile) => {
+            println!("cargo:rerun-if-changed={}", lockfile.display());
+            shared_crate_versions(&lockfile)
+        }
+        None => {
+            println!("cargo:warning=Couldn't find Cargo.lock, plugins may not load");
+            "unknown".to_string()
+        }
+    };
+    println!("cargo:rustc-env=RENOVATIO_DEPENDENCIES={}", dependencies);
+}
+
+/// The version of the compiler cargo builds with, through its wrapper if it has one
+fn rustc_version() -> Option<String> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let wrapper = std::env::var("RUSTC_WRAPPER")
+        .ok()
+        .filter(|wrapper| !wrapper.is_empty());
+
+    let mut commands = Vec::new();
+    if let Some(wrapper) = wrapper {
+        let mut command = Command::new(wrapper);
+        command.arg(&rustc);
+        commands.push(command);
+    }
+    commands.push(Command::new(&rustc));
+    commands.push(Command::new("rustc"));
+
+    commands.into_iter().find_map(|mut command| {
+        let output = command.arg("--version").output().ok()?;
+        let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !version.is_empty()).then_some(version)
+    })
+}
+
+/// The lockfile of the workspace being built, which is the plugin's workspace when a plugin
+/// is built. The build directory is usually inside it.
+fn find_lockfile() -> Option<PathBuf> {
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR")?);
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR")?);
+
+    out_dir
+        .ancestors()
+        .chain(manifest_dir.ancestors())
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|lockfile| {
+            std::fs::read_to_string(lockfile)
+                .is_ok_and(|lock| lock.contains("name = \"renovatio\""))
+        })
+}
+
+/// The locked versions of the shared crates, i.e. "flume 0.11.1, serde 1.0.228"
+fn shared_crate_versions(lockfile: &Path) -> String {
+    let lock = std::fs::read_to_string(lockfile).unwrap_or_default();
+
+    let mut versions = Vec::new();
+    let mut name = None;
+    for line in lock.lines() {
+        if let Some(value) = line.strip_prefix("name = ") {
+            name = Some(value.trim_matches('"').to_string());
+        } else if let Some(value) = line.strip_prefix("version = ")
+            && let Some(name) = name.take()
+            && SHARED_CRATES.contains(&name.as_str())
+        {
+            versions.push(format!("{} {}", name, value.trim_matches('"')));
+        }
+    }
+
+    versions.sort();
+    versions.join(", ")
+}
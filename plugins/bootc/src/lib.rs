@@ -1,4 +1,9 @@
-use renovatio::{Plugin, PluginProgress, execute};
+use renovatio::settings::{PluginSettings, Setting};
+use renovatio::{
+    Availability, CancellationToken, Capabilities, Deployment, OS_KIND, Outcome, PendingUpdate,
+    Plugin, PluginError, PluginEvent, Privileges, RollbackTarget, RunOrder, Selection, execute,
+    execute_streaming, is_ostree_booted,
+};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -38,7 +43,7 @@ pub struct Image {
 #[serde(rename_all = "camelCase")]
 pub struct Status {
     pub booted: Booted,
-    pub rollback: Rollback,
+    pub rollback: Option<Rollback>,
     pub rollback_queued: bool,
     pub staged: Option<Staged>,
     #[serde(rename = "type")]
@@ -172,92 +177,432 @@ impl Plugin for Bootc {
     }
 
     fn version(&self) -> &str {
-        // TODO: implement versioning
-        "0.1.0"
+        env!("CARGO_PKG_VERSION")
     }
 
-    /// Uupd conflicts with all other plugins
-    fn conflicts(&self, _plugin_name: &str) -> bool {
-        true
+    fn icon_name(&self) -> &str {
+        "drive-harddisk-system-symbolic"
     }
 
-    /// Run uupd
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool {
-        let mut pgrss = PluginProgress::new(self.name());
-        pgrss.progress = 0;
-        pgrss.pulse = true;
-        pgrss.status = "Checking for updates...".to_string();
-        let _ = tx.send(pgrss.clone());
+    fn author(&self) -> &str {
+        env!("CARGO_PKG_AUTHORS")
+    }
+
+    fn homepage(&self) -> &str {
+        env!("CARGO_PKG_HOMEPAGE")
+    }
+
+    /// bootc upgrades the OS image with pkexec
+    fn privileges(&self) -> Privileges {
+        Privileges::Administrator
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            check: true,
+            selective_update: true,
+            rollback: true,
+            changelog: true,
+            background_check: true,
+        }
+    }
+
+    fn settings(&self) -> Vec<Setting> {
+        vec![Setting::bool(
+            "pkexec",
+            "Authenticate With pkexec",
+            "Authenticate before upgrading or rolling back. Checking for updates never asks. \
+             Turn off if renovatio already runs as root, or bootc is allowed to run without it",
+            true,
+        )]
+    }
+
+    /// uupd also updates the OS, and rpm-ostree manages the same deployments
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        matches!(plugin_name, "uupd" | "rpm-ostree")
+    }
+
+    /// Update the OS first, so the other plugins update on top of it
+    fn run_order(&self) -> RunOrder {
+        RunOrder::before(&["*"])
+    }
+
+    /// The OS deployments, which rpm-ostree also manages
+    fn resources(&self) -> Vec<String> {
+        vec!["os".to_string()]
+    }
+
+    /// The OS has to be booted from an image bootc manages
+    fn is_available(&self) -> Availability {
+        if !is_ostree_booted() {
+            return Availability::Unavailable("This system isn't managed by bootc".to_string());
+        }
+        Availability::requires("bootc")
+    }
+
+    /// Report the OS image update bootc found, if any.
+    ///
+    /// Checking never asks for authentication. `bootc upgrade --check` only records the
+    /// update when renovatio runs as root, otherwise this reports what the last upgrade or
+    /// check as root found, and nothing if bootc won't show its status to the user.
+    fn check(&self) -> Vec<PendingUpdate> {
+        let mut updates = Vec::new();
+
+        // Fetch the metadata of the new image, if we're allowed to
+        let _ = execute("bootc upgrade --check");
+
+        if let Ok(status) = read_status()
+            && let Some(cached_update) = status.status.booted.cached_update
+        {
+            let mut update = PendingUpdate::new(&status.spec.image.image);
+            update.kind = Some(OS_KIND.to_string());
+            update.current_version = Some(status.status.booted.image.version);
+            update.new_version = Some(cached_update.version);
+            updates.push(update);
+        }
+
+        updates
+    }
+
+    /// List the packages the staged OS image changes, or describe the cached image
+    fn changelog(&self, _update: &PendingUpdate) -> Option<String> {
+        let status = read_status().ok()?;
+        let booted = &status.status.booted;
+
+        // The packages can only be compared once the new image has been pulled
+        if let Some(staged) = &status.status.staged {
+            let (stdout, _stderr, rc) = execute(&format!(
+                "rpm-ostree db diff {} {}",
+                booted.ostree.checksum, staged.ostree.checksum
+            ));
+            if rc == 0 && !stdout.trim().is_empty() {
+                return Some(stdout.trim().to_string());
+            }
+        }
+
+        // Fall back to what the image's labels say about it
+        let cached_update = booted.cached_update.as_ref()?;
+        Some(format!(
+            "Version {} → {}\nBuilt {}\nDigest {}",
+            booted.image.version,
+            cached_update.version,
+            cached_update.timestamp,
+            cached_update.image_digest
+        ))
+    }
+
+    /// Upgrade the booted bootc image, which takes effect on the next boot
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        let _ = tx.send(PluginEvent::pulse());
+        let _ = tx.send(PluginEvent::status("Checking for updates..."));
 
         // Check the status to see if there's an update available
-        if let Some(status) = get_status() {
-            // Check to see if there's an update
-            if status.status.booted.cached_update.is_some() {
-                // There is an update cached, so we need to reboot
+        let status = get_status(settings)?;
 
-                let new_version = status.status.booted.cached_update.unwrap().version;
+        // Check to see if the OS image was deselected
+        if !selection.contains(&status.spec.image.image) {
+            let _ = tx.send(PluginEvent::status("OS upgrade skipped"));
+            return Ok(());
+        }
 
-                // There's a cached update, so a) update the progress and b) run the upgrade
-                pgrss.status = format!("OS upgrade to {} pending reboot", new_version);
-                pgrss.progress = 100;
-                pgrss.pulse = false;
-                pgrss.reboot_required = true;
+        let mut item = PendingUpdate::new(&status.spec.image.image);
+        item.kind = Some(OS_KIND.to_string());
+        item.current_version = Some(status.status.booted.image.version.clone());
+        item.new_version = status
+            .status
+            .booted
+            .cached_update
+            .as_ref()
+            .map(|cached_update| cached_update.version.clone());
+
+        // Checking only finds the update, so it still has to be applied unless it's staged
+        if let Some(new_version) = staged_version(&status.status) {
+            item.new_version = Some(new_version.clone());
+
+            let _ = tx.send(PluginEvent::StepStarted { item: item.clone() });
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: Outcome::Succeeded,
+            });
+            let _ = tx.send(PluginEvent::RebootRequired {
+                reason: format!("OS upgrade to {} pending reboot", new_version),
+            });
+            let _ = tx.send(PluginEvent::progress(1.0));
+        } else {
+            let _ = tx.send(PluginEvent::StepStarted { item: item.clone() });
+
+            let cmd = bootc(settings, "upgrade");
+            let (stdout, stderr, rc) = execute_streaming(&cmd, cancel, |stream, line| {
+                let _ = tx.send(PluginEvent::log(stream, line));
+            });
+
+            if rc != 0 {
+                let _ = tx.send(PluginEvent::StepFinished {
+                    item: item.name,
+                    outcome: failed(cancel, &stderr),
+                });
+                let _ = tx.send(PluginEvent::status("Failed to upgrade the OS"));
+                return Err(PluginError::from_command(&cmd, &stderr, rc));
+            }
 
-                let _ = tx.send(pgrss.clone());
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: Outcome::Succeeded,
+            });
+            if stdout.contains("No changes in") {
+                let _ = tx.send(PluginEvent::status("No updates available"));
             } else {
-                // There's a cached update, so a) update the progress and b) run the upgrade
-                pgrss.status = "Checking for updates...".to_string();
-                let _ = tx.send(pgrss.clone());
-
-                let (stdout, stderr, rc) = upgrade();
-                if rc == 0 {
-                    pgrss.stdout = Some(stdout.clone());
-                } else {
-                    pgrss.stderr = Some(stderr.clone());
-                }
-
-                // When the upgrade is complete, signal that we're done.
-                pgrss.progress = 100;
-                pgrss.pulse = false;
-
-                if stdout.contains("No changes in") {
-                    pgrss.status = "No updates available".to_string();
-                } else {
-                    pgrss.reboot_required = true;
-                    pgrss.status = "OS upgrade pending reboot".to_string();
-                }
-
-                let _ = tx.send(pgrss.clone());
+                let _ = tx.send(PluginEvent::RebootRequired {
+                    reason: "OS upgrade pending reboot".to_string(),
+                });
             }
-        } else {
-            println!("No status available");
+
+            // When the upgrade is complete, signal that we're done.
+            let _ = tx.send(PluginEvent::progress(1.0));
+        }
+
+        Ok(())
+    }
+
+    /// The image before the last upgrade, which bootc keeps as the rollback deployment
+    fn rollback_target(&self) -> Option<RollbackTarget> {
+        let status = read_status().ok()?;
+        let booted = &status.status.booted;
+        let rollback = status.status.rollback?;
+
+        // The rollback deployment's image isn't known if it wasn't deployed from one
+        let rollback_image: Option<Image3> = serde_json::from_value(rollback.image).ok();
+
+        Some(RollbackTarget {
+            booted: deployment(Some(&booted.image), &booted.ostree.checksum, booted.pinned),
+            rollback: deployment(
+                rollback_image.as_ref(),
+                &rollback.ostree.checksum,
+                rollback.pinned,
+            ),
+            queued: status.status.rollback_queued,
+        })
+    }
+
+    /// Boot the rollback deployment next time
+    extern "Rust" fn rollback(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        settings: &PluginSettings,
+    ) -> Result<(), PluginError> {
+        let _ = tx.send(PluginEvent::pulse());
+        let _ = tx.send(PluginEvent::status("Rolling back..."));
+
+        let status = get_status(settings)?;
+        let mut item = PendingUpdate::new(&status.spec.image.image);
+        item.kind = Some(OS_KIND.to_string());
+        item.current_version = Some(status.status.booted.image.version);
+        item.new_version = status
+            .status
+            .rollback
+            .and_then(|rollback| serde_json::from_value::<Image3>(rollback.image).ok())
+            .map(|image| image.version);
+        let _ = tx.send(PluginEvent::StepStarted { item: item.clone() });
+
+        let cmd = bootc(settings, "rollback");
+        let (_stdout, stderr, rc) =
+            execute_streaming(&cmd, &CancellationToken::new(), |stream, line| {
+                let _ = tx.send(PluginEvent::log(stream, line));
+            });
+        if rc != 0 {
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: Outcome::Failed {
+                    error: Some(stderr.clone()),
+                },
+            });
+            let _ = tx.send(PluginEvent::status("Failed to roll back the OS"));
+            return Err(PluginError::from_command(&cmd, &stderr, rc));
         }
 
-        true
+        let _ = tx.send(PluginEvent::StepFinished {
+            item: item.name,
+            outcome: Outcome::Succeeded,
+        });
+        let _ = tx.send(PluginEvent::RebootRequired {
+            reason: "The previous OS image boots next time".to_string(),
+        });
+        let _ = tx.send(PluginEvent::progress(1.0));
+        let _ = tx.send(PluginEvent::status("Rolled back!"));
+
+        Ok(())
+    }
+}
+
+// Export the plugin so renovatio can load it
+renovatio::export_plugin!(Bootc);
+
+/// The command line running bootc with `args`, with pkexec unless it's turned off
+fn bootc(settings: &PluginSettings, args: &str) -> String {
+    if settings.bool("pkexec") {
+        format!("pkexec bootc {}", args)
+    } else {
+        format!("bootc {}", args)
     }
 }
 
-// Export a function to create an instance of the plugin
-#[unsafe(no_mangle)]
-pub fn create_plugin() -> *mut dyn Plugin {
-    Box::into_raw(Box::new(Bootc))
+/// Get the status, authenticating with pkexec unless it's turned off
+fn get_status(settings: &PluginSettings) -> Result<Root, PluginError> {
+    parse_status(&bootc(settings, "status --json"))
+}
+
+/// Get the status without asking for authentication, which fails unless renovatio runs as
+/// root or bootc lets the user read it
+fn read_status() -> Result<Root, PluginError> {
+    parse_status("bootc status --json")
 }
 
-fn get_status() -> Option<Root> {
-    // execute `bootc status --json`
-    let (stdout, _stderr, rc) = execute("pkexec bootc status --json");
+/// Run `cmd`, which prints the status as JSON
+fn parse_status(cmd: &str) -> Result<Root, PluginError> {
+    let (stdout, stderr, rc) = execute(cmd);
 
     if rc != 0 {
-        return None;
+        return Err(PluginError::from_command(cmd, &stderr, rc));
     }
 
     // deserialize the json to a Root structure
-    let root: Root = serde_json::from_str(&stdout).expect("Failed to deserialize JSON");
+    serde_json::from_str(&stdout).map_err(|error| PluginError::Parse(format!("{}: {}", cmd, error)))
+}
+
+/// The version staged for the next boot, if it's the newest update bootc found.
+///
+/// An upgrade stages the update it downloads, so there's nothing left to do but reboot, but
+/// a check only records the update as cached without staging it.
+fn staged_version(status: &Status) -> Option<String> {
+    let staged = status.staged.as_ref()?;
+    match &status.booted.cached_update {
+        Some(cached_update) if cached_update.image_digest != staged.image.image_digest => None,
+        _ => Some(staged.image.version.clone()),
+    }
+}
+
+/// Describe a deployment of `image`, or of the ostree commit `checksum` if it has no image
+fn deployment(image: Option<&Image3>, checksum: &str, pinned: bool) -> Deployment {
+    let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_string());
+
+    Deployment {
+        version: image.and_then(|image| non_empty(&image.version)),
+        digest: image
+            .and_then(|image| non_empty(&image.image_digest))
+            .or_else(|| non_empty(checksum)),
+        pinned,
+    }
+}
 
-    Some(root)
+/// Cancelling the update makes `bootc` fail too, so only a real failure is reported as one
+fn failed(cancel: &CancellationToken, stderr: &str) -> Outcome {
+    if cancel.is_cancelled() {
+        Outcome::Cancelled
+    } else {
+        Outcome::Failed {
+            error: Some(stderr.to_string()),
+        }
+    }
 }
 
-/// Run the `bootc upgrade` command
-fn upgrade() -> (String, String, i32) {
-    execute("pkexec bootc upgrade")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOTED: &str = r#"{
+        "image": {
+            "image": {"image": "ghcr.io/ublue-os/bluefin:stable", "transport": "registry", "signature": "insecure"},
+            "version": "42.20250601.0",
+            "timestamp": "2025-06-01T04:12:09Z",
+            "imageDigest": "sha256:1111111111111111111111111111111111111111111111111111111111111111",
+            "architecture": "amd64"
+        },
+        "cachedUpdate": {
+            "image": {"image": "ghcr.io/ublue-os/bluefin:stable", "transport": "registry", "signature": "insecure"},
+            "version": "42.20250602.0",
+            "timestamp": "2025-06-02T04:10:51Z",
+            "imageDigest": "sha256:2222222222222222222222222222222222222222222222222222222222222222",
+            "architecture": "amd64"
+        },
+        "incompatible": false,
+        "pinned": false,
+        "softRebootCapable": false,
+        "store": "ostreeContainer",
+        "ostree": {"checksum": "4c8a2a5e0d1f", "deploySerial": 0, "stateroot": "default"}
+    }"#;
+
+    /// A staged deployment of the image with `digest`
+    fn staged(digest: &str) -> String {
+        format!(
+            r#"{{
+                "image": {{
+                    "image": {{"image": "ghcr.io/ublue-os/bluefin:stable", "transport": "registry", "signature": "insecure"}},
+                    "version": "42.20250602.0",
+                    "timestamp": "2025-06-02T04:10:51Z",
+                    "imageDigest": "{}",
+                    "architecture": "amd64"
+                }},
+                "cachedUpdate": null,
+                "incompatible": false,
+                "pinned": false,
+                "softRebootCapable": false,
+                "store": "ostreeContainer",
+                "ostree": {{"checksum": "9f3b7d21c6e0", "deploySerial": 0, "stateroot": "default"}}
+            }}"#,
+            digest
+        )
+    }
+
+    /// The output of `bootc status --json`, with the `staged` deployment
+    fn status(staged: &str) -> Status {
+        let json = format!(
+            r#"{{
+                "apiVersion": "org.containers.bootc/v1",
+                "kind": "BootcHost",
+                "metadata": {{"name": "host"}},
+                "spec": {{
+                    "bootOrder": "default",
+                    "image": {{"image": "ghcr.io/ublue-os/bluefin:stable", "transport": "registry", "signature": "insecure"}}
+                }},
+                "status": {{
+                    "staged": {},
+                    "booted": {},
+                    "rollback": null,
+                    "rollbackQueued": false,
+                    "type": "bootcHost"
+                }}
+            }}"#,
+            staged, BOOTED
+        );
+        serde_json::from_str::<Root>(&json).unwrap().status
+    }
+
+    #[test]
+    fn a_checked_update_still_has_to_be_applied() {
+        let status = status("null");
+        assert!(status.booted.cached_update.is_some());
+        assert_eq!(staged_version(&status), None);
+    }
+
+    #[test]
+    fn a_staged_update_only_needs_a_reboot() {
+        let status = status(&staged(
+            "sha256:2222222222222222222222222222222222222222222222222222222222222222",
+        ));
+        assert_eq!(staged_version(&status).as_deref(), Some("42.20250602.0"));
+    }
+
+    #[test]
+    fn a_newer_update_than_the_staged_one_is_applied() {
+        let status = status(&staged(
+            "sha256:3333333333333333333333333333333333333333333333333333333333333333",
+        ));
+        assert_eq!(staged_version(&status), None);
+    }
 }
@@ -1,6 +1,11 @@
-use renovatio::{Plugin, PluginProgress, execute};
+use renovatio::settings::{PluginSettings, Setting};
+use renovatio::{
+    Availability, CancellationToken, Capabilities, Outcome, PendingUpdate, Plugin, PluginError,
+    PluginEvent, Privileges, Selection, Stream, execute, execute_streaming,
+};
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 // brew outdated --json
 // {
@@ -46,6 +51,15 @@ impl Formulae {
             pinned_version: None,
         }
     }
+
+    /// Convert an outdated formula or cask into a pending update
+    pub fn to_pending_update(&self, kind: &str) -> PendingUpdate {
+        let mut update = PendingUpdate::new(&self.name);
+        update.kind = Some(kind.to_string());
+        update.current_version = self.installed_versions.last().cloned();
+        update.new_version = Some(self.current_version.clone());
+        update
+    }
 }
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Outdated {
@@ -81,136 +95,304 @@ impl Plugin for Brew {
     }
 
     fn version(&self) -> &str {
-        // TODO: implement versioning
-        "0.1.0"
+        env!("CARGO_PKG_VERSION")
     }
 
-    /// Uupd conflicts with all other plugins
-    fn conflicts(&self, plugin_name: &str) -> bool {
-        plugin_name == "uupd"
+    fn icon_name(&self) -> &str {
+        "utilities-terminal-symbolic"
     }
 
-    /// Run uupd
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool {
-        let mut pgrss = PluginProgress::new(self.name());
+    fn author(&self) -> &str {
+        env!("CARGO_PKG_AUTHORS")
+    }
 
-        // run a `brew update`
-        pgrss.status = "Updating brew...".to_string();
-        pgrss.progress = 5;
-        let _ = tx.send(pgrss.clone());
+    fn homepage(&self) -> &str {
+        env!("CARGO_PKG_HOMEPAGE")
+    }
 
-        let (mut stdout, mut stderr, mut success) = update();
-        if success != 0 {
-            pgrss.status = "Failed to update brew".to_string();
-            pgrss.stderr = Some(stderr);
-            let _ = tx.send(pgrss.clone());
-            return false;
+    /// Homebrew is owned by the user
+    fn privileges(&self) -> Privileges {
+        Privileges::User
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            check: true,
+            selective_update: true,
+            rollback: false,
+            changelog: true,
+            background_check: true,
         }
+    }
 
-        // Get a list of outdated packages
-        pgrss.status = "Getting outdated packages...".to_string();
-        pgrss.stdout = Some(stdout.clone());
-        pgrss.progress = 10;
-        let _ = tx.send(pgrss.clone());
+    fn settings(&self) -> Vec<Setting> {
+        vec![Setting::bool(
+            "casks",
+            "Upgrade Casks",
+            "Upgrade applications installed with brew install --cask, as well as formulae",
+            true,
+        )]
+    }
 
-        (stdout, stderr, success) = get_outdated();
+    /// uupd also upgrades brew
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        plugin_name == "uupd"
+    }
+
+    /// Homebrew lives apart from the OS and the other package managers
+    fn resources(&self) -> Vec<String> {
+        vec!["brew".to_string()]
+    }
+
+    /// Homebrew has to be installed
+    fn is_available(&self) -> Availability {
+        Availability::requires("brew")
+    }
+
+    /// List the outdated formulae and casks
+    fn check(&self) -> Vec<PendingUpdate> {
+        let mut updates = Vec::new();
+
+        let (stdout, _stderr, success) = get_outdated();
         if success != 0 {
-            pgrss.status = "Failed to get outdated brew".to_string();
-            pgrss.stderr = Some(stderr);
-            let _ = tx.send(pgrss.clone());
-            return false;
+            return updates;
         }
-        let outdated: Outdated = serde_json::from_str(&stdout).unwrap();
-
-        // calculate the total progress based on the number of outdated formulae and casks
-        let total_progress = outdated.formulae.len() + outdated.casks.len();
 
-        println!("total_progress: {}", total_progress);
-        if total_progress > 0 {
-            // Figure out how much each step should progress, minus the first two hard-coded steps
-            let step_progress = 100 / total_progress;
+        let settings = PluginSettings::load(self.name(), &self.settings());
 
-            // Upgrade each formulae
+        if let Ok(outdated) = serde_json::from_str::<Outdated>(&stdout) {
             for formulae in outdated.formulae {
-                pgrss.status = format!("Upgrading formulae {}...", formulae.name);
-                pgrss.stdout = None;
-                pgrss.stderr = None;
-                let _ = tx.send(pgrss.clone());
-
-                (stdout, stderr, success) = upgrade_formulae(&formulae.name);
-                if success != 0 {
-                    pgrss.status = format!("Failed to upgrade formulae {}", formulae.name);
-                    pgrss.stderr = Some(stderr.clone());
-                    let _ = tx.send(pgrss.clone());
-                    // Continue updating
+                updates.push(formulae.to_pending_update("formula"));
+            }
+            if settings.bool("casks") {
+                for cask in outdated.casks {
+                    updates.push(cask.to_pending_update("cask"));
                 }
+            }
+        }
 
-                pgrss.progress += step_progress as u32;
-                pgrss.stdout = Some(stdout.clone());
-                if !stderr.is_empty() {
-                    pgrss.stderr = Some(stderr.clone());
+        updates
+    }
+
+    /// Describe how far the formula or cask jumps, and what it is
+    fn changelog(&self, update: &PendingUpdate) -> Option<String> {
+        let new_version = update.new_version.as_deref()?;
+        let mut changelog = match update.current_version.as_deref() {
+            Some(current_version) => format!(
+                "{} {} → {} ({})",
+                update.name,
+                current_version,
+                new_version,
+                version_jump(current_version, new_version)
+            ),
+            None => format!("{} {}", update.name, new_version),
+        };
+
+        // Homebrew keeps the descriptions locally, so this works offline
+        let (kind, flag) = if update.kind.as_deref() == Some("cask") {
+            ("casks", "--cask")
+        } else {
+            ("formulae", "--formula")
+        };
+        let (stdout, _stderr, success) =
+            execute(&format!("brew info --json=v2 {} {}", flag, update.name));
+        if success == 0
+            && let Ok(info) = serde_json::from_str::<Value>(&stdout)
+        {
+            let info = &info[kind][0];
+            for field in ["desc", "homepage"] {
+                if let Some(value) = info[field].as_str() {
+                    changelog = format!("{}\n\n{}", changelog, value);
                 }
-                let _ = tx.send(pgrss.clone());
             }
+        }
 
-            // Upgrade each cask
-            for cask in outdated.casks {
-                pgrss.status = format!("Upgrading cask {}...", cask.name);
-                pgrss.stdout = None;
-                pgrss.stderr = None;
-                let _ = tx.send(pgrss.clone());
-
-                (stdout, stderr, success) = upgrade_cask(&cask.name);
-                if success != 0 {
-                    pgrss.status = format!("Failed to upgrade cask {}", cask.name);
-                    pgrss.stderr = Some(stderr.clone());
-                    let _ = tx.send(pgrss.clone());
-                    // Continue updating
-                }
+        Some(changelog)
+    }
 
-                pgrss.stdout = Some(stdout.clone());
-                if !stderr.is_empty() {
-                    pgrss.stderr = Some(stderr.clone());
-                }
-                pgrss.progress += step_progress as u32;
-                let _ = tx.send(pgrss.clone());
+    /// Update Homebrew, then upgrade the selected outdated formulae, and casks if enabled
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        // run a `brew update`
+        let _ = tx.send(PluginEvent::status("Updating brew..."));
+        let _ = tx.send(PluginEvent::progress(0.05));
+
+        let (_stdout, stderr, success) = update(cancel, |stream, line| {
+            let _ = tx.send(PluginEvent::log(stream, line));
+        });
+        if success != 0 {
+            let _ = tx.send(PluginEvent::status("Failed to update brew"));
+            return Err(PluginError::from_command("brew update", &stderr, success));
+        }
+
+        // Get a list of outdated packages
+        let _ = tx.send(PluginEvent::status("Getting outdated packages..."));
+        let _ = tx.send(PluginEvent::progress(0.1));
+
+        let (stdout, stderr, success) = get_outdated();
+        if success != 0 {
+            let _ = tx.send(PluginEvent::status("Failed to get outdated brew"));
+            for line in stderr.lines() {
+                let _ = tx.send(PluginEvent::log(Stream::Stderr, line));
             }
+            return Err(PluginError::from_command(
+                "brew outdated --json",
+                &stderr,
+                success,
+            ));
+        }
+        let mut outdated: Outdated = serde_json::from_str(&stdout)
+            .map_err(|error| PluginError::Parse(format!("brew outdated --json: {}", error)))?;
+
+        // Only upgrade the formulae and casks that were selected
+        outdated
+            .formulae
+            .retain(|formulae| selection.contains(&formulae.name));
+        outdated.casks.retain(|cask| selection.contains(&cask.name));
+
+        // Leave the casks alone, unless they're upgraded too
+        if !settings.bool("casks") {
+            outdated.casks.clear();
         }
 
-        // Done!
-        pgrss.progress = 100;
-        pgrss.stdout = None;
-        pgrss.stderr = None;
+        // Upgrade each formulae, then each cask
+        let upgrades: Vec<PendingUpdate> = outdated
+            .formulae
+            .iter()
+            .map(|formulae| formulae.to_pending_update("formula"))
+            .chain(
+                outdated
+                    .casks
+                    .iter()
+                    .map(|cask| cask.to_pending_update("cask")),
+            )
+            .collect();
+
+        // Share the rest of the progress between the formulae and casks
+        let total = upgrades.len();
+
+        let mut failed = Vec::new();
+        for (index, item) in upgrades.into_iter().enumerate() {
+            if cancel.is_cancelled() {
+                break;
+            }
 
-        pgrss.status = "Upgrade completed!".to_string();
-        let _ = tx.send(pgrss.clone());
+            let kind = item.kind.clone().unwrap_or_default();
+            let _ = tx.send(PluginEvent::status(&format!(
+                "Upgrading {} {}...",
+                kind, item.name
+            )));
+            let _ = tx.send(PluginEvent::StepStarted { item: item.clone() });
+
+            let on_line = |stream, line: &str| {
+                let _ = tx.send(PluginEvent::log(stream, line));
+            };
+            let (_stdout, stderr, success) = if kind == "cask" {
+                upgrade_cask(&item.name, cancel, on_line)
+            } else {
+                upgrade_formulae(&item.name, cancel, on_line)
+            };
+
+            // An upgrade that was interrupted by cancelling didn't fail
+            if success != 0 && cancel.is_cancelled() {
+                let _ = tx.send(PluginEvent::StepFinished {
+                    item: item.name,
+                    outcome: Outcome::Cancelled,
+                });
+                break;
+            }
+            let outcome = if success == 0 {
+                Outcome::Succeeded
+            } else {
+                let _ = tx.send(PluginEvent::status(&format!(
+                    "Failed to upgrade {} {}",
+                    kind, item.name
+                )));
+                // Continue updating
+                failed.push(item.name.clone());
+                Outcome::Failed {
+                    error: Some(stderr),
+                }
+            };
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome,
+            });
+
+            let _ = tx.send(PluginEvent::progress(
+                0.1 + 0.9 * (index + 1) as f64 / total as f64,
+            ));
+        }
 
-        true
+        // Done!
+        let _ = tx.send(PluginEvent::progress(1.0));
+        let _ = tx.send(PluginEvent::status("Upgrade completed!"));
+
+        // The update failed if any formulae or cask failed to upgrade
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginError::PartialFailure { failed })
+        }
     }
 }
 
-// Export a function to create an instance of the plugin
-#[unsafe(no_mangle)]
-pub fn create_plugin() -> *mut dyn Plugin {
-    Box::into_raw(Box::new(Brew))
-}
+// Export the plugin so renovatio can load it
+renovatio::export_plugin!(Brew);
 
 fn get_outdated() -> (String, String, i32) {
     let cmd = "brew outdated --json";
     execute(cmd)
 }
 
-fn update() -> (String, String, i32) {
+/// Classify the jump between two versions, i.e. "minor release" from 1.2.3 to 1.3.0
+fn version_jump(from: &str, to: &str) -> &'static str {
+    // Homebrew appends its own revision, i.e. 1.2.3_1, when it rebuilds a formula
+    fn parts(version: &str) -> Vec<&str> {
+        version
+            .split('_')
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .collect()
+    }
+    let (from, to) = (parts(from), parts(to));
+
+    let length = from.len().max(to.len());
+    let changed =
+        (0..length).find(|&index| from.get(index).unwrap_or(&"0") != to.get(index).unwrap_or(&"0"));
+    match changed {
+        Some(0) => "major release",
+        Some(1) => "minor release",
+        Some(_) => "patch release",
+        None => "rebuild",
+    }
+}
+
+fn update(cancel: &CancellationToken, on_line: impl FnMut(Stream, &str)) -> (String, String, i32) {
     // run a `brew update`
-    execute("brew update")
+    execute_streaming("brew update", cancel, on_line)
 }
 
-fn upgrade_formulae(formula: &str) -> (String, String, i32) {
+fn upgrade_formulae(
+    formula: &str,
+    cancel: &CancellationToken,
+    on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
     // run a `brew upgrade <formula> --dry-run`
-    execute(&format!("brew upgrade {}", formula))
+    execute_streaming(&format!("brew upgrade {}", formula), cancel, on_line)
 }
 
-fn upgrade_cask(cask: &str) -> (String, String, i32) {
+fn upgrade_cask(
+    cask: &str,
+    cancel: &CancellationToken,
+    on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
     // run a `brew upgrade --cask <formula> --dry-run`
-    execute(&format!("brew upgrade --cask {}", cask))
+    execute_streaming(&format!("brew upgrade --cask {}", cask), cancel, on_line)
 }
@@ -1,4 +1,8 @@
-use renovatio::{Plugin, PluginProgress, execute};
+use renovatio::settings::{PluginSettings, Setting};
+use renovatio::{
+    Availability, CancellationToken, Capabilities, Outcome, PendingUpdate, Plugin, PluginError,
+    PluginEvent, Privileges, RunOrder, Selection, Stream, execute, execute_streaming, in_container,
+};
 
 // Implementation of distrobox
 pub struct Distrobox;
@@ -13,70 +17,158 @@ impl Plugin for Distrobox {
     }
 
     fn version(&self) -> &str {
-        // TODO: implement versioning
-        "0.1.0"
+        env!("CARGO_PKG_VERSION")
     }
 
-    /// Uupd conflicts with all other plugins
-    fn conflicts(&self, _plugin_name: &str) -> bool {
-        true
+    fn icon_name(&self) -> &str {
+        "package-x-generic-symbolic"
     }
 
-    /// Run uupd
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool {
-        let mut pgrss = PluginProgress::new(self.name());
+    fn author(&self) -> &str {
+        env!("CARGO_PKG_AUTHORS")
+    }
 
-        // List the distroboxes
-        let distroboxes = list();
+    fn homepage(&self) -> &str {
+        env!("CARGO_PKG_HOMEPAGE")
+    }
 
-        // calculate the total progress based on the number of outdated formulae and casks
-        let total_progress = distroboxes.len();
+    /// Distroboxes are owned by the user
+    fn privileges(&self) -> Privileges {
+        Privileges::User
+    }
 
-        // Figure out how much each step should progress
-        let mut step_progress = 100;
-        if total_progress > 0 {
-            step_progress = 100 / total_progress;
+    /// Finding out if a distrobox is outdated means starting it and asking its package
+    /// manager, so they're upgraded without checking first
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            check: false,
+            selective_update: false,
+            rollback: false,
+            changelog: false,
+            background_check: false,
         }
+    }
 
-        for distrobox in distroboxes {
-            pgrss.status = format!("Upgrading distrobox {}...", distrobox);
-            pgrss.stdout = None;
-            pgrss.stderr = None;
-            let _ = tx.send(pgrss.clone());
-
-            let (stdout, stderr, success) = upgrade(&distrobox);
-            if success != 0 {
-                pgrss.status = format!("Failed to upgrade distrobox {}", distrobox);
-                pgrss.stderr = Some(stderr.clone());
-                let _ = tx.send(pgrss.clone());
-                // Continue updating
+    fn settings(&self) -> Vec<Setting> {
+        vec![Setting::list(
+            "skip",
+            "Skipped Distroboxes",
+            "The names of the distroboxes not to upgrade, separated by commas",
+            &[],
+        )]
+    }
+
+    /// uupd also upgrades distroboxes
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        plugin_name == "uupd"
+    }
+
+    /// Upgrade the containers last, once the host is up to date
+    fn run_order(&self) -> RunOrder {
+        RunOrder::after(&["*"])
+    }
+
+    /// Containers are updated apart from the host
+    fn resources(&self) -> Vec<String> {
+        vec!["distrobox".to_string()]
+    }
+
+    /// distrobox has to be installed, and distroboxes are managed from the host
+    fn is_available(&self) -> Availability {
+        if in_container() {
+            return Availability::Unavailable(
+                "Distroboxes can't be updated from inside a container".to_string(),
+            );
+        }
+        Availability::requires("distrobox")
+    }
+
+    /// distrobox has no way to list pending updates without upgrading
+    fn check(&self) -> Vec<PendingUpdate> {
+        Vec::new()
+    }
+
+    /// Upgrade the packages inside each distrobox, except the skipped ones. Nothing was
+    /// listed to select, so the selection is ignored.
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        _selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        // List the distroboxes
+        let mut distroboxes = list();
+
+        // Only upgrade the distroboxes that aren't skipped
+        let skip = settings.list("skip");
+        distroboxes.retain(|distrobox| !skip.contains(distrobox));
+
+        // calculate the total progress based on the number of distroboxes
+        let total = distroboxes.len();
+
+        let mut failed = Vec::new();
+        for (index, distrobox) in distroboxes.into_iter().enumerate() {
+            if cancel.is_cancelled() {
+                break;
             }
 
-            pgrss.progress += step_progress as u32;
-            pgrss.stdout = Some(stdout.clone());
-            if !stderr.is_empty() {
-                pgrss.stderr = Some(stderr.clone());
+            let _ = tx.send(PluginEvent::status(&format!(
+                "Upgrading distrobox {}...",
+                distrobox
+            )));
+            let mut item = PendingUpdate::new(&distrobox);
+            item.kind = Some("container".to_string());
+            let _ = tx.send(PluginEvent::StepStarted { item });
+
+            let (_stdout, stderr, success) = upgrade(&distrobox, cancel, |stream, line| {
+                let _ = tx.send(PluginEvent::log(stream, line));
+            });
+
+            // An upgrade that was interrupted by cancelling didn't fail
+            if success != 0 && cancel.is_cancelled() {
+                let _ = tx.send(PluginEvent::StepFinished {
+                    item: distrobox,
+                    outcome: Outcome::Cancelled,
+                });
+                break;
             }
-            let _ = tx.send(pgrss.clone());
+            let outcome = if success == 0 {
+                Outcome::Succeeded
+            } else {
+                let _ = tx.send(PluginEvent::status(&format!(
+                    "Failed to upgrade distrobox {}",
+                    distrobox
+                )));
+                // Continue updating
+                failed.push(distrobox.clone());
+                Outcome::Failed {
+                    error: Some(stderr),
+                }
+            };
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: distrobox,
+                outcome,
+            });
+
+            let _ = tx.send(PluginEvent::progress((index + 1) as f64 / total as f64));
         }
 
         // Done!
-        pgrss.progress = 100;
-        pgrss.stdout = None;
-        pgrss.stderr = None;
-
-        pgrss.status = "Upgrade completed!".to_string();
-        let _ = tx.send(pgrss.clone());
-
-        true
+        let _ = tx.send(PluginEvent::progress(1.0));
+        let _ = tx.send(PluginEvent::status("Upgrade completed!"));
+
+        // The update failed if any distrobox failed to upgrade
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginError::PartialFailure { failed })
+        }
     }
 }
 
-// Export a function to create an instance of the plugin
-#[unsafe(no_mangle)]
-pub fn create_plugin() -> *mut dyn Plugin {
-    Box::into_raw(Box::new(Distrobox))
-}
+// Export the plugin so renovatio can load it
+renovatio::export_plugin!(Distrobox);
 
 pub fn list() -> Vec<String> {
     let mut boxes = Vec::new();
@@ -86,15 +178,22 @@ pub fn list() -> Vec<String> {
     }
 
     for line in stdout.lines().skip(1) {
-        let cols = line
-            .split('|')
-            .map(|s| s.trim().to_string())
-            .collect::<Vec<String>>();
-        boxes.push(cols[1].to_string());
+        // Skip anything that isn't a row of the table
+        if let Some(name) = line.split('|').nth(1) {
+            boxes.push(name.trim().to_string());
+        }
     }
     boxes
 }
 
-pub fn upgrade(name: &str) -> (String, String, i32) {
-    execute(format!("distrobox upgrade {}", name).as_str())
+pub fn upgrade(
+    name: &str,
+    cancel: &CancellationToken,
+    on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
+    execute_streaming(
+        format!("distrobox upgrade {}", name).as_str(),
+        cancel,
+        on_line,
+    )
 }
@@ -1,4 +1,19 @@
-use renovatio::{Plugin, PluginProgress, execute};
+use flate2::read::GzDecoder;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::reader::Reader;
+use renovatio::settings::{PluginSettings, Setting};
+use renovatio::{
+    Availability, CancellationToken, Capabilities, Outcome, PendingUpdate, Plugin, PluginError,
+    PluginEvent, Privileges, Selection, Stream, execute, execute_streaming,
+};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The most releases to show the notes of
+const MAX_RELEASES: usize = 5;
 
 pub struct Flatpak;
 // Implementation of flatpak
@@ -13,107 +28,622 @@ impl Plugin for Flatpak {
     }
 
     fn version(&self) -> &str {
-        // TODO: implement versioning
-        "0.1.0"
+        env!("CARGO_PKG_VERSION")
     }
 
-    /// Uupd conflicts with all other plugins
-    fn conflicts(&self, _plugin_name: &str) -> bool {
-        true
+    fn icon_name(&self) -> &str {
+        "view-app-grid-symbolic"
     }
 
-    /// Run uupd
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool {
-        let mut pgrss = PluginProgress::new(self.name());
+    fn author(&self) -> &str {
+        env!("CARGO_PKG_AUTHORS")
+    }
 
-        // Find the system flatpaks needing update
-        let system = list_updates(true);
-        let user = list_updates(false);
+    fn homepage(&self) -> &str {
+        env!("CARGO_PKG_HOMEPAGE")
+    }
 
-        // calculate the total progress based on the number of outdated flatpaks
-        let total_progress = system.len() + user.len();
+    /// Updating system-wide flatpaks asks polkit
+    fn privileges(&self) -> Privileges {
+        Privileges::Administrator
+    }
 
-        // Figure out how much each step should progress
-        let mut step_progress = 100;
-        if total_progress > 0 {
-            step_progress = 100 / total_progress;
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            check: true,
+            selective_update: true,
+            rollback: false,
+            changelog: true,
+            background_check: true,
         }
+    }
 
-        let mut pgrss_clone = pgrss.clone();
-        let mut upgrade = |flatpak: &str, installation: &str| {
-            pgrss_clone.status = format!("Upgrading {} flatpak {}...", installation, flatpak);
-            pgrss_clone.stdout = None;
-            pgrss_clone.stderr = None;
-            let _ = tx.send(pgrss_clone.clone());
+    fn settings(&self) -> Vec<Setting> {
+        vec![Setting::choice(
+            "installations",
+            "Installations",
+            "Which flatpak installations to update",
+            &["system and user", "system", "user"],
+            "system and user",
+        )]
+    }
 
-            let (stdout, stderr, success) = upgrade_flatpak(flatpak);
+    /// uupd also updates flatpaks
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        plugin_name == "uupd"
+    }
 
-            if success != 0 {
-                pgrss_clone.status =
-                    format!("Failed to upgrade {} flatpak {}", installation, flatpak);
-                pgrss_clone.stderr = Some(stderr.clone());
-                let _ = tx.send(pgrss_clone.clone());
-                // Continue updating
-            }
+    /// Flatpaks don't touch anything the other plugins update
+    fn resources(&self) -> Vec<String> {
+        vec!["flatpak".to_string()]
+    }
+
+    /// Flatpak has to be installed
+    fn is_available(&self) -> Availability {
+        Availability::requires("flatpak")
+    }
+
+    /// List the system and user flatpaks with updates available
+    fn check(&self) -> Vec<PendingUpdate> {
+        let settings = PluginSettings::load(self.name(), &self.settings());
+        let (system, user) = installations(&settings);
+
+        let mut updates = Vec::new();
+        if system {
+            updates.extend(list_updates(true));
+        }
+        if user {
+            updates.extend(list_updates(false));
+        }
+        updates
+    }
+
+    /// Show the release notes of the versions newer than the installed one, from the appstream
+    /// metadata flatpak keeps for each remote
+    fn changelog(&self, update: &PendingUpdate) -> Option<String> {
+        let (system, application) = parse_update_name(&update.name);
+        appstream_files(system).iter().find_map(|path| {
+            release_notes(
+                &read_appstream(path)?,
+                application,
+                update.current_version.as_deref(),
+            )
+        })
+    }
+
+    /// Upgrade the selected flatpaks, in the system and user installations to update
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        // Find the system and user flatpaks needing update, in the installations to update
+        let (update_system, update_user) = installations(settings);
+        let mut system = if update_system {
+            list_updates(true)
+        } else {
+            Vec::new()
+        };
+        let mut user = if update_user {
+            list_updates(false)
+        } else {
+            Vec::new()
+        };
+
+        // Only upgrade the flatpaks that were selected
+        system.retain(|flatpak| selection.contains(&flatpak.name));
+        user.retain(|flatpak| selection.contains(&flatpak.name));
+
+        // calculate the total progress based on the number of outdated flatpaks
+        let total = system.len() + user.len();
+        let mut done = 0;
+
+        let mut upgrade = |update: &PendingUpdate| {
+            let (system, flatpak) = parse_update_name(&update.name);
+            let installation = if system { "system" } else { "user" };
+            let _ = tx.send(PluginEvent::status(&format!(
+                "Upgrading {} flatpak {}...",
+                installation, flatpak
+            )));
+            let _ = tx.send(PluginEvent::StepStarted {
+                item: update.clone(),
+            });
+
+            let (_stdout, stderr, success) =
+                upgrade_flatpak(flatpak, system, cancel, |stream, line| {
+                    let _ = tx.send(PluginEvent::log(stream, line));
+                });
 
-            pgrss_clone.progress += step_progress as u32;
-            pgrss_clone.stdout = Some(stdout.clone());
-            if !stderr.is_empty() {
-                pgrss_clone.stderr = Some(stderr.clone());
+            // An upgrade that was interrupted by cancelling didn't fail
+            if success != 0 && cancel.is_cancelled() {
+                let _ = tx.send(PluginEvent::StepFinished {
+                    item: update.name.clone(),
+                    outcome: Outcome::Cancelled,
+                });
+                return true;
             }
-            let _ = tx.send(pgrss_clone.clone());
+
+            let outcome = if success == 0 {
+                Outcome::Succeeded
+            } else {
+                let _ = tx.send(PluginEvent::status(&format!(
+                    "Failed to upgrade {} flatpak {}",
+                    installation, flatpak
+                )));
+                // Continue updating
+                Outcome::Failed {
+                    error: Some(stderr),
+                }
+            };
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: update.name.clone(),
+                outcome,
+            });
+
+            done += 1;
+            let _ = tx.send(PluginEvent::progress(done as f64 / total as f64));
+
+            success == 0
         };
 
-        pgrss.status = "Upgrading flatpaks...".to_string();
-        pgrss.progress = 0;
-        pgrss.stdout = None;
-        pgrss.stderr = None;
-        let _ = tx.send(pgrss.clone());
+        let _ = tx.send(PluginEvent::status("Upgrading flatpaks..."));
+        let _ = tx.send(PluginEvent::progress(0.0));
 
-        for flatpak in system {
-            upgrade(&flatpak, "system");
+        let mut failed = Vec::new();
+        for flatpak in system.into_iter().chain(user) {
+            if cancel.is_cancelled() {
+                break;
+            }
+            if !upgrade(&flatpak) {
+                failed.push(flatpak.name);
+            }
         }
 
-        for flatpak in user {
-            upgrade(&flatpak, "user");
+        let _ = tx.send(PluginEvent::progress(1.0));
+        let _ = tx.send(PluginEvent::status("Upgrade completed!"));
+
+        // The update failed if any flatpak failed to upgrade
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginError::PartialFailure { failed })
         }
+    }
+}
 
-        pgrss.progress = 100;
-        pgrss.stdout = None;
-        pgrss.stderr = None;
+// Export the plugin so renovatio can load it
+renovatio::export_plugin!(Flatpak);
 
-        pgrss.status = "Upgrade completed!".to_string();
-        let _ = tx.send(pgrss.clone());
+/// Whether to update the system and the user installation
+fn installations(settings: &PluginSettings) -> (bool, bool) {
+    match settings.choice("installations") {
+        "system" => (true, false),
+        "user" => (false, true),
+        _ => (true, true),
+    }
+}
 
-        true
+/// The name of the pending update of `application` in the system or user installation. An
+/// application can be installed in both, so the name says which, i.e. "user/org.gnome.Maps".
+fn update_name(application: &str, system: bool) -> String {
+    format!("{}/{}", if system { "system" } else { "user" }, application)
+}
+
+/// Whether the pending update `name` is in the system installation, and its application
+fn parse_update_name(name: &str) -> (bool, &str) {
+    match name.split_once('/') {
+        Some(("user", application)) => (false, application),
+        Some((_, application)) => (true, application),
+        None => (true, name),
     }
 }
 
-// Export a function to create an instance of the plugin
-#[unsafe(no_mangle)]
-pub fn create_plugin() -> *mut dyn Plugin {
-    Box::into_raw(Box::new(Flatpak))
+fn installation_flag(system: bool) -> &'static str {
+    if system { "--system" } else { "--user" }
 }
 
-fn list_updates(system: bool) -> Vec<String> {
+fn list_updates(system: bool) -> Vec<PendingUpdate> {
     let mut updates = Vec::new();
-    let mut flag = "--system";
-    if !system {
-        flag = "--user";
-    }
-    let (stdout, _stderr, success) =
-        execute(format!("flatpak remote-ls --updates --columns=application {}", flag).as_str());
+    let flag = installation_flag(system);
+    let (stdout, _stderr, success) = execute(
+        format!(
+            "flatpak remote-ls --updates --columns=application,version,download-size {}",
+            flag
+        )
+        .as_str(),
+    );
     if success != 0 {
         return updates;
     }
 
+    let installed = list_installed(system);
+
     for line in stdout.lines().skip(1) {
-        updates.push(line.to_string());
+        let cols = line.split('\t').map(|s| s.trim()).collect::<Vec<&str>>();
+
+        let mut update = PendingUpdate::new(&update_name(cols[0], system));
+        update.kind = Some(
+            if system {
+                "system flatpak"
+            } else {
+                "user flatpak"
+            }
+            .to_string(),
+        );
+        update.current_version = installed.get(cols[0]).cloned();
+        update.new_version = cols.get(1).filter(|v| !v.is_empty()).map(|v| v.to_string());
+        update.size = cols.get(2).filter(|v| !v.is_empty()).map(|v| v.to_string());
+        updates.push(update);
     }
 
     updates
 }
 
-fn upgrade_flatpak(name: &str) -> (String, String, i32) {
-    execute(format!("flatpak update --noninteractive -y {}", name).as_str())
+/// Map the installed flatpaks to their installed version
+fn list_installed(system: bool) -> HashMap<String, String> {
+    let mut installed = HashMap::new();
+    let flag = installation_flag(system);
+    let (stdout, _stderr, success) =
+        execute(format!("flatpak list --columns=application,version {}", flag).as_str());
+    if success != 0 {
+        return installed;
+    }
+
+    for line in stdout.lines() {
+        let cols = line.split('\t').map(|s| s.trim()).collect::<Vec<&str>>();
+        if let [application, version] = cols[..]
+            && !version.is_empty()
+        {
+            installed.insert(application.to_string(), version.to_string());
+        }
+    }
+
+    installed
+}
+
+fn upgrade_flatpak(
+    application: &str,
+    system: bool,
+    cancel: &CancellationToken,
+    on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
+    execute_streaming(
+        format!(
+            "flatpak update --noninteractive -y {} {}",
+            installation_flag(system),
+            application
+        )
+        .as_str(),
+        cancel,
+        on_line,
+    )
+}
+
+/// The appstream metadata of each remote of the system or user installation
+fn appstream_files(system: bool) -> Vec<PathBuf> {
+    let dir = if system {
+        PathBuf::from("/var/lib/flatpak")
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".local/share")
+            })
+            .join("flatpak")
+    };
+
+    // The metadata is kept in appstream/<remote>/<arch>/active
+    let subdirs = |dir: &Path| -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default()
+    };
+    subdirs(&dir.join("appstream"))
+        .iter()
+        .flat_map(|remote| subdirs(remote))
+        .map(|arch| arch.join("active"))
+        .collect()
+}
+
+/// Read the appstream metadata in `dir`, which may only have been kept compressed
+fn read_appstream(dir: &Path) -> Option<String> {
+    if let Ok(xml) = std::fs::read_to_string(dir.join("appstream.xml")) {
+        return Some(xml);
+    }
+
+    let file = File::open(dir.join("appstream.xml.gz")).ok()?;
+    let mut xml = String::new();
+    GzDecoder::new(file).read_to_string(&mut xml).ok()?;
+    Some(xml)
+}
+
+/// A release of an application, as its appstream metadata lists it
+#[derive(Debug, Default)]
+struct Release {
+    version: Option<String>,
+    date: Option<String>,
+
+    /// A line per paragraph and list item, with the translations left out
+    description: String,
+}
+
+/// The notes of the releases of `application` after `installed`, newest first
+fn release_notes(xml: &str, application: &str, installed: Option<&str>) -> Option<String> {
+    let mut notes = Vec::new();
+    for release in releases(xml, application)? {
+        // Releases without a version can't be told apart
+        let Some(version) = release.version else {
+            continue;
+        };
+        if Some(version.as_str()) == installed || notes.len() == MAX_RELEASES {
+            break;
+        }
+
+        let mut heading = version;
+        if let Some(date) = release.date {
+            heading = format!("{} ({})", heading, date);
+        }
+
+        let description = release
+            .description
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<String>>()
+            .join("\n");
+        notes.push(match description.is_empty() {
+            true => heading,
+            false => format!("{}\n{}", heading, description),
+        });
+    }
+
+    (!notes.is_empty()).then(|| notes.join("\n\n"))
+}
+
+/// The releases of `application` in the appstream metadata `xml`, in the order they're
+/// listed, which is newest first
+fn releases(xml: &str, application: &str) -> Option<Vec<Release>> {
+    let mut reader = Reader::from_str(xml);
+
+    // The names of the elements the reader is in, outermost first
+    let mut elements: Vec<Vec<u8>> = Vec::new();
+    // The depth of the translated element being skipped, if the reader is in one
+    let mut translation: Option<usize> = None;
+
+    let mut id = String::new();
+    let mut releases: Vec<Release> = Vec::new();
+
+    loop {
+        let text = match reader.read_event().ok()? {
+            Event::Start(start) => {
+                let name = start.local_name().as_ref().to_vec();
+                if translation.is_none()
+                    && start.try_get_attribute("xml:lang").ok().flatten().is_some()
+                {
+                    translation = Some(elements.len());
+                }
+
+                if name == b"component" {
+                    id.clear();
+                    releases.clear();
+                } else if name == b"release" && is_child_of(&elements, b"releases") {
+                    releases.push(release(&start));
+                } else if name == b"li"
+                    && translation.is_none()
+                    && is_in_release_description(&elements)
+                    && let Some(release) = releases.last_mut()
+                {
+                    release.description.push_str("• ");
+                }
+                elements.push(name);
+                continue;
+            }
+            Event::Empty(start) => {
+                if start.local_name().as_ref() == b"release" && is_child_of(&elements, b"releases")
+                {
+                    releases.push(release(&start));
+                }
+                continue;
+            }
+            Event::End(_) => {
+                let name = elements.pop().unwrap_or_default();
+                if translation == Some(elements.len()) {
+                    translation = None;
+                } else if name == b"component" {
+                    let id = id.trim();
+                    if id == application || id.strip_suffix(".desktop") == Some(application) {
+                        return Some(releases);
+                    }
+                } else if translation.is_none()
+                    && matches!(name.as_slice(), b"p" | b"li")
+                    && is_in_release_description(&elements)
+                    && let Some(release) = releases.last_mut()
+                {
+                    release.description.push('\n');
+                }
+                continue;
+            }
+            Event::Text(text) => text.decode().ok()?.replace('\n', " "),
+            Event::CData(text) => text.decode().ok()?.replace('\n', " "),
+            Event::GeneralRef(reference) => match reference.resolve_char_ref().ok()? {
+                Some(character) => character.to_string(),
+                None => {
+                    let name = reference.decode().ok()?;
+                    quick_xml::escape::resolve_predefined_entity(&name)?.to_string()
+                }
+            },
+            Event::Eof => return None,
+            _ => continue,
+        };
+
+        if matches!(elements.as_slice(), [.., parent, name] if parent == b"component" && name == b"id")
+        {
+            id.push_str(&text);
+        } else if translation.is_none()
+            && is_in_release_description(&elements)
+            && let Some(release) = releases.last_mut()
+        {
+            release.description.push_str(&text);
+        }
+    }
+}
+
+/// A release, without its description, given the tag it starts with
+fn release(start: &BytesStart) -> Release {
+    let attribute = |name: &str| {
+        let attribute = start.try_get_attribute(name).ok()??;
+        Some(attribute.unescape_value().ok()?.to_string())
+    };
+
+    Release {
+        version: attribute("version"),
+        date: attribute("date"),
+        description: String::new(),
+    }
+}
+
+/// Determine if the element the reader is at is a child of an element called `name`
+fn is_child_of(elements: &[Vec<u8>], name: &[u8]) -> bool {
+    elements
+        .last()
+        .is_some_and(|parent| parent.as_slice() == name)
+}
+
+/// Determine if the reader is in the description of a release
+fn is_in_release_description(elements: &[Vec<u8>]) -> bool {
+    elements
+        .iter()
+        .skip_while(|name| name.as_slice() != b"release")
+        .any(|name| name == b"description")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Part of Flathub's appstream metadata, with its translations
+    const APPSTREAM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<components version="0.8" origin="flathub">
+  <component type="desktop">
+    <id>org.gnome.Calculator.desktop</id>
+    <name>Calculator</name>
+    <releases>
+      <release version="47.0" timestamp="1726444800"/>
+    </releases>
+  </component>
+  <component type="desktop">
+    <id type="desktop">org.gnome.Maps.desktop</id>
+    <name>Maps</name>
+    <name xml:lang="de">Karten</name>
+    <description>
+      <p>Maps gives you quick access to maps all across the world.</p>
+    </description>
+    <provides>
+      <id>org.gnome.Calculator.desktop</id>
+    </provides>
+    <releases>
+      <release version="46.2" date="2024-05-28" type="stable">
+        <description>
+          <p>Fixes &amp; improvements:</p>
+          <p xml:lang="de">Fehlerbehebungen &amp; Verbesserungen:</p>
+          <ul>
+            <li>Don&#8217;t crash when
+              searching offline</li>
+            <li xml:lang="de">Nicht abstürzen, wenn offline gesucht wird</li>
+            <li>Show &lt;b&gt; in place names</li>
+          </ul>
+        </description>
+      </release>
+      <release>
+        <description><p>A release without a version</p></description>
+      </release>
+      <release version="46.1" date="2024-04-20"/>
+      <release version="46.0" date="2024-03-15">
+        <description><p>GNOME 46</p></description>
+      </release>
+    </releases>
+  </component>
+</components>
+"#;
+
+    #[test]
+    fn shows_the_releases_after_the_installed_one() {
+        assert_eq!(
+            release_notes(APPSTREAM, "org.gnome.Maps", Some("46.0")).as_deref(),
+            Some(
+                "46.2 (2024-05-28)\n\
+                 Fixes & improvements:\n\
+                 • Don\u{2019}t crash when searching offline\n\
+                 • Show <b> in place names\n\
+                 \n\
+                 46.1 (2024-04-20)"
+            )
+        );
+    }
+
+    #[test]
+    fn shows_the_newest_releases_if_the_installed_one_isnt_listed() {
+        let notes = release_notes(APPSTREAM, "org.gnome.Maps", None).unwrap_or_default();
+        assert!(notes.ends_with("46.0 (2024-03-15)\nGNOME 46"), "{}", notes);
+        assert!(!notes.contains("without a version"), "{}", notes);
+    }
+
+    #[test]
+    fn shows_at_most_max_releases() {
+        let releases = (0..10)
+            .map(|minor| format!("<release version=\"1.{}\"/>", 9 - minor))
+            .collect::<String>();
+        let xml = format!(
+            "<components><component><id>org.example.App</id><releases>{}</releases></component></components>",
+            releases
+        );
+
+        let notes = release_notes(&xml, "org.example.App", None).unwrap_or_default();
+        assert_eq!(notes, "1.9\n\n1.8\n\n1.7\n\n1.6\n\n1.5");
+    }
+
+    #[test]
+    fn only_matches_the_component_of_the_application() {
+        assert_eq!(
+            release_notes(APPSTREAM, "org.gnome.Calculator", None).as_deref(),
+            Some("47.0")
+        );
+        assert_eq!(release_notes(APPSTREAM, "org.gnome.Weather", None), None);
+        assert_eq!(
+            release_notes("<components><component>", "org.gnome.Maps", None),
+            None
+        );
+    }
+
+    #[test]
+    fn tells_the_installations_apart() {
+        let system = update_name("org.gnome.Maps", true);
+        let user = update_name("org.gnome.Maps", false);
+        assert_ne!(system, user);
+        assert_eq!(parse_update_name(&system), (true, "org.gnome.Maps"));
+        assert_eq!(parse_update_name(&user), (false, "org.gnome.Maps"));
+    }
+
+    #[test]
+    fn reads_compressed_appstream() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("flatpak-appstream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut encoder = GzEncoder::new(
+            File::create(dir.join("appstream.xml.gz")).unwrap(),
+            Compression::default(),
+        );
+        encoder.write_all(APPSTREAM.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let xml = read_appstream(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(xml.as_deref(), Some(APPSTREAM));
+    }
 }
@@ -1,4 +1,14 @@
-use renovatio::{Plugin, PluginProgress, execute};
+use renovatio::{
+    Availability, CancellationToken, Capabilities, Deployment, OS_KIND, Outcome, PendingUpdate,
+    Plugin, PluginError, PluginEvent, Privileges, RollbackTarget, RunOrder, Selection, Stream,
+    execute, execute_streaming, is_ostree_booted,
+};
+
+use renovatio::settings::PluginSettings;
+use serde_json::Value;
+
+/// The name of the pending update that represents the OS itself
+const OS_UPDATE: &str = "os";
 
 // Implementation of rpm-ostree
 pub struct RpmOstree;
@@ -13,83 +23,448 @@ impl Plugin for RpmOstree {
     }
 
     fn version(&self) -> &str {
-        // TODO: implement versioning
-        "0.1.0"
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn icon_name(&self) -> &str {
+        "drive-harddisk-system-symbolic"
+    }
+
+    fn author(&self) -> &str {
+        env!("CARGO_PKG_AUTHORS")
     }
 
-    /// Uupd conflicts with all other plugins
-    fn conflicts(&self, _plugin_name: &str) -> bool {
-        true
+    fn homepage(&self) -> &str {
+        env!("CARGO_PKG_HOMEPAGE")
     }
 
-    /// Run uupd
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool {
-        let mut pgrss = PluginProgress::new(self.name());
-        pgrss.pulse = true;
-        // TODO: need to figure out how to use the _pulse_ on the ProgressBar
+    /// rpm-ostree asks polkit before changing deployments
+    fn privileges(&self) -> Privileges {
+        Privileges::Administrator
+    }
+
+    /// `rpm-ostree upgrade --check` goes through polkit, which may ask for a password, so it
+    /// isn't checked in the background
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            check: true,
+            selective_update: true,
+            rollback: true,
+            changelog: true,
+            background_check: false,
+        }
+    }
 
-        // Figure out how much each step should progress
-        let total_progress = 2;
-        let step_progress = 100 / total_progress;
+    /// uupd also updates the OS, and bootc manages the same deployments
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        matches!(plugin_name, "uupd" | "bootc")
+    }
 
-        pgrss.status = "Checking for updates ...".to_string();
-        pgrss.stdout = None;
-        pgrss.stderr = None;
-        let _ = tx.send(pgrss.clone());
+    /// Update the OS first, so the other plugins update on top of it
+    fn run_order(&self) -> RunOrder {
+        RunOrder::before(&["*"])
+    }
 
-        let (mut stdout, mut stderr, mut success) = download();
+    /// The OS deployments, which bootc also manages
+    fn resources(&self) -> Vec<String> {
+        vec!["os".to_string()]
+    }
+
+    /// The OS has to be an rpm-ostree deployment
+    fn is_available(&self) -> Availability {
+        if !is_ostree_booted() {
+            return Availability::Unavailable(
+                "This system isn't managed by rpm-ostree".to_string(),
+            );
+        }
+        Availability::requires("rpm-ostree")
+    }
+
+    /// Check for an OS update without downloading it
+    fn check(&self) -> Vec<PendingUpdate> {
+        let mut updates = Vec::new();
+
+        // `rpm-ostree upgrade --check` exits with 77 if there is no update available
+        let (stdout, _stderr, success) = check();
+        if success != 0 {
+            return updates;
+        }
+
+        let mut update = os_update();
+        update.current_version = booted_version();
+        update.new_version = stdout
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Version:"))
+            .and_then(|version| version.split_whitespace().next())
+            .map(|version| version.to_string());
+        updates.push(update);
+
+        updates
+    }
+
+    /// List the packages the OS update changes
+    fn changelog(&self, update: &PendingUpdate) -> Option<String> {
+        if update.name != OS_UPDATE {
+            return None;
+        }
+
+        // A downloaded update is staged, so it can be compared with the booted deployment
+        let (stdout, _stderr, success) = execute("rpm-ostree db diff");
+        if success == 0 && !stdout.trim().is_empty() {
+            return Some(stdout.trim().to_string());
+        }
+
+        // Otherwise, ask the remote what would change
+        let (stdout, _stderr, success) = execute("rpm-ostree upgrade --preview");
+        (success == 0).then(|| stdout.trim().to_string())
+    }
+
+    /// Download and stage the OS update, which is booted into on the next restart
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        _settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        // Check to see if the OS update was deselected
+        if !selection.contains(OS_UPDATE) {
+            let _ = tx.send(PluginEvent::status("OS upgrade skipped"));
+            return Ok(());
+        }
+
+        let _ = tx.send(PluginEvent::pulse());
+        let _ = tx.send(PluginEvent::status("Checking for updates ..."));
+
+        // Keep track of the version we're upgrading from
+        let mut item = os_update();
+        item.current_version = booted_version();
+        let _ = tx.send(PluginEvent::StepStarted { item: item.clone() });
+
+        let (_stdout, stderr, success) = download(cancel, |stream, line| {
+            let _ = tx.send(PluginEvent::log(stream, line));
+        });
         // TODO: need to change execute to return the exit code. And then only fail
         // if the exit code indicates an error (like no network) rather than no update available.
         if success != 0 {
-            pgrss.status = "Failed to check/download updates".to_string();
-            pgrss.stderr = Some(stderr.clone());
-            let _ = tx.send(pgrss.clone());
+            let _ = tx.send(PluginEvent::status("Failed to check/download updates"));
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: failed(cancel, &stderr),
+            });
             // if we failed to download, we can't continue
-            return false;
+            return Err(PluginError::from_command(
+                "rpm-ostree upgrade --download-only",
+                &stderr,
+                success,
+            ));
+        }
+
+        // Don't start installing the update if we were cancelled while downloading it
+        if cancel.is_cancelled() {
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: Outcome::Cancelled,
+            });
+            return Ok(());
         }
-        println!("{}", stdout);
 
         // We've downloaded the update, so let's install it.
-        pgrss.status = "Installing OS update ...".to_string();
-        pgrss.progress = step_progress as u32;
-        pgrss.stdout = None;
-        pgrss.stderr = None;
-        let _ = tx.send(pgrss.clone());
+        let _ = tx.send(PluginEvent::status("Installing OS update ..."));
+        let _ = tx.send(PluginEvent::progress(0.5));
 
-        (stdout, stderr, success) = upgrade();
+        let (stdout, stderr, success) = upgrade(cancel, |stream, line| {
+            let _ = tx.send(PluginEvent::log(stream, line));
+        });
         if success != 0 {
-            pgrss.status = "Failed to install OS update...".to_string();
-            pgrss.stderr = Some(stderr.clone());
-            let _ = tx.send(pgrss.clone());
-            // if we failed to download, we can't continue
-            return false;
+            let _ = tx.send(PluginEvent::status("Failed to install OS update..."));
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: failed(cancel, &stderr),
+            });
+            return Err(PluginError::from_command(
+                "rpm-ostree upgrade",
+                &stderr,
+                success,
+            ));
         }
-        println!("{}", stdout);
 
         // Done!
-        pgrss.progress = 100;
-        pgrss.stdout = None;
-        pgrss.stderr = None;
+        let _ = tx.send(PluginEvent::progress(1.0));
+        let _ = tx.send(PluginEvent::status("Upgrade complete!"));
+        let _ = tx.send(PluginEvent::StepFinished {
+            item: item.name,
+            outcome: Outcome::Succeeded,
+        });
+        if !stdout.contains("No upgrade available") {
+            let _ = tx.send(PluginEvent::RebootRequired {
+                reason: "The OS update is staged for the next boot".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The deployment after the booted one, which is what rpm-ostree rolls back to
+    fn rollback_target(&self) -> Option<RollbackTarget> {
+        let (stdout, _stderr, success) = execute("rpm-ostree status --json");
+        if success != 0 {
+            return None;
+        }
+
+        rollback_target(&serde_json::from_str(&stdout).ok()?)
+    }
 
-        pgrss.status = "Upgrade complete!".to_string();
-        let _ = tx.send(pgrss.clone());
+    /// Make the previous deployment the one that boots next time
+    extern "Rust" fn rollback(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        _settings: &PluginSettings,
+    ) -> Result<(), PluginError> {
+        let _ = tx.send(PluginEvent::pulse());
+        let _ = tx.send(PluginEvent::status("Rolling back..."));
 
-        true
+        let mut item = os_update();
+        if let Some(target) = self.rollback_target() {
+            item.current_version = target.booted.version;
+            item.new_version = target.rollback.version;
+        }
+        let _ = tx.send(PluginEvent::StepStarted { item: item.clone() });
+
+        let (_stdout, stderr, success) = execute_streaming(
+            "rpm-ostree rollback",
+            &CancellationToken::new(),
+            |stream, line| {
+                let _ = tx.send(PluginEvent::log(stream, line));
+            },
+        );
+        if success != 0 {
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: item.name,
+                outcome: Outcome::Failed {
+                    error: Some(stderr.clone()),
+                },
+            });
+            let _ = tx.send(PluginEvent::status("Failed to roll back the OS"));
+            return Err(PluginError::from_command(
+                "rpm-ostree rollback",
+                &stderr,
+                success,
+            ));
+        }
+
+        let _ = tx.send(PluginEvent::StepFinished {
+            item: item.name,
+            outcome: Outcome::Succeeded,
+        });
+        let _ = tx.send(PluginEvent::RebootRequired {
+            reason: "The previous deployment boots next time".to_string(),
+        });
+        let _ = tx.send(PluginEvent::progress(1.0));
+        let _ = tx.send(PluginEvent::status("Rolled back!"));
+
+        Ok(())
     }
 }
 
-// Export a function to create an instance of the plugin
-#[unsafe(no_mangle)]
-pub fn create_plugin() -> *mut dyn Plugin {
-    Box::into_raw(Box::new(RpmOstree))
+// Export the plugin so renovatio can load it
+renovatio::export_plugin!(RpmOstree);
+
+fn check() -> (String, String, i32) {
+    // run `rpm-ostree upgrade --check`
+    execute("rpm-ostree upgrade --check")
+}
+
+/// The pending update that represents the OS itself
+fn os_update() -> PendingUpdate {
+    let mut update = PendingUpdate::new(OS_UPDATE);
+    update.kind = Some(OS_KIND.to_string());
+    update
 }
 
-fn download() -> (String, String, i32) {
+/// Get the version of the booted deployment
+fn booted_version() -> Option<String> {
+    let (stdout, _stderr, success) = execute("rpm-ostree status --booted --json");
+    if success != 0 {
+        return None;
+    }
+
+    let status: Value = serde_json::from_str(&stdout).ok()?;
+    status["deployments"][0]["version"]
+        .as_str()
+        .map(|version| version.to_string())
+}
+
+/// What rolling back would go back to, given the output of `rpm-ostree status --json`
+fn rollback_target(status: &Value) -> Option<RollbackTarget> {
+    // The deployments are listed in boot order, so one ahead of the booted deployment is
+    // pending: it boots next time
+    let deployments = status["deployments"].as_array()?;
+    let booted = deployments
+        .iter()
+        .position(|deployment| deployment["booted"].as_bool() == Some(true))?;
+
+    // Rolling back makes the deployment after the booted one pending, so a pending
+    // deployment older than the booted one has already been rolled back to. A staged
+    // deployment is always an update, which is only finalized when shutting down.
+    let timestamp = |deployment: &Value| deployment["timestamp"].as_u64();
+    let queued = deployments[..booted]
+        .first()
+        .filter(|pending| pending["staged"].as_bool() != Some(true))
+        .filter(|pending| {
+            matches!(
+                (timestamp(pending), timestamp(&deployments[booted])),
+                (Some(pending), Some(booted)) if pending < booted
+            )
+        });
+
+    let rollback = match queued {
+        Some(pending) => pending,
+        None => deployments.get(booted + 1)?,
+    };
+
+    Some(RollbackTarget {
+        booted: deployment(&deployments[booted]),
+        rollback: deployment(rollback),
+        queued: queued.is_some(),
+    })
+}
+
+/// Describe a deployment from `rpm-ostree status --json`
+fn deployment(deployment: &Value) -> Deployment {
+    let field = |key: &str| deployment[key].as_str().map(|value| value.to_string());
+
+    Deployment {
+        version: field("version"),
+        digest: field("container-image-reference-digest").or_else(|| field("checksum")),
+        pinned: deployment["pinned"].as_bool().unwrap_or_default(),
+    }
+}
+
+fn download(
+    cancel: &CancellationToken,
+    on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
     // run `rpm-ostree upgrade --download-only`
-    execute("rpm-ostree upgrade --download-only")
+    execute_streaming("rpm-ostree upgrade --download-only", cancel, on_line)
 }
 
-fn upgrade() -> (String, String, i32) {
+fn upgrade(cancel: &CancellationToken, on_line: impl FnMut(Stream, &str)) -> (String, String, i32) {
     // run `rpm-ostree upgrade`
-    execute("rpm-ostree upgrade")
+    execute_streaming("rpm-ostree upgrade", cancel, on_line)
+}
+
+/// How an item ended when its command failed, which it does when the update is cancelled
+fn failed(cancel: &CancellationToken, stderr: &str) -> Outcome {
+    if cancel.is_cancelled() {
+        Outcome::Cancelled
+    } else {
+        Outcome::Failed {
+            error: Some(stderr.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deployment as `rpm-ostree status --json` lists it, leaving out the packages
+    fn listed(version: &str, timestamp: u64, booted: bool, staged: bool) -> Value {
+        serde_json::json!({
+            "id": format!("fedora-{}", version),
+            "osname": "fedora",
+            "version": version,
+            "timestamp": timestamp,
+            "checksum": format!("{:0>64}", timestamp),
+            "container-image-reference": "ostree-image-signed:docker://quay.io/fedora/fedora-silverblue:42",
+            "container-image-reference-digest": format!("sha256:{:0>64}", timestamp),
+            "booted": booted,
+            "staged": staged,
+            "pinned": false,
+        })
+    }
+
+    /// The output of `rpm-ostree status --json` listing `deployments`
+    fn status(deployments: Vec<Value>) -> Value {
+        serde_json::json!({
+            "deployments": deployments,
+            "transaction": null,
+            "cached-update": null,
+            "update-driver": null,
+        })
+    }
+
+    fn versions(target: &RollbackTarget) -> (Option<&str>, Option<&str>, bool) {
+        (
+            target.booted.version.as_deref(),
+            target.rollback.version.as_deref(),
+            target.queued,
+        )
+    }
+
+    #[test]
+    fn rolls_back_to_the_deployment_after_the_booted_one() {
+        let status = status(vec![
+            listed("42.20250602.0", 1748822400, true, false),
+            listed("42.20250601.0", 1748736000, false, false),
+        ]);
+
+        let target = rollback_target(&status).unwrap();
+        assert_eq!(
+            versions(&target),
+            (Some("42.20250602.0"), Some("42.20250601.0"), false)
+        );
+        assert_eq!(
+            target.rollback.digest.as_deref(),
+            Some("sha256:0000000000000000000000000000000000000000000000000000001748736000")
+        );
+    }
+
+    #[test]
+    fn an_older_pending_deployment_has_been_rolled_back_to() {
+        let status = status(vec![
+            listed("42.20250601.0", 1748736000, false, false),
+            listed("42.20250602.0", 1748822400, true, false),
+        ]);
+
+        let target = rollback_target(&status).unwrap();
+        assert_eq!(
+            versions(&target),
+            (Some("42.20250602.0"), Some("42.20250601.0"), true)
+        );
+    }
+
+    #[test]
+    fn a_pending_update_isnt_a_rollback() {
+        // A staged update...
+        let staged = status(vec![
+            listed("42.20250603.0", 1748908800, false, true),
+            listed("42.20250602.0", 1748822400, true, false),
+            listed("42.20250601.0", 1748736000, false, false),
+        ]);
+        let target = rollback_target(&staged).unwrap();
+        assert_eq!(
+            versions(&target),
+            (Some("42.20250602.0"), Some("42.20250601.0"), false)
+        );
+
+        // ...or one that was deployed without staging it
+        let deployed = status(vec![
+            listed("42.20250603.0", 1748908800, false, false),
+            listed("42.20250602.0", 1748822400, true, false),
+            listed("42.20250601.0", 1748736000, false, false),
+        ]);
+        let target = rollback_target(&deployed).unwrap();
+        assert_eq!(
+            versions(&target),
+            (Some("42.20250602.0"), Some("42.20250601.0"), false)
+        );
+    }
+
+    #[test]
+    fn nothing_to_roll_back_to_with_one_deployment() {
+        let status = status(vec![listed("42.20250602.0", 1748822400, true, false)]);
+        assert!(rollback_target(&status).is_none());
+    }
 }
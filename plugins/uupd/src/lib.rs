@@ -1,11 +1,11 @@
-use renovatio::{Plugin, PluginProgress};
+use renovatio::settings::PluginSettings;
+use renovatio::{
+    Availability, CancellationToken, Capabilities, PendingUpdate, Plugin, PluginError, PluginEvent,
+    Privileges, Selection, Stream, execute_streaming,
+};
 
 use serde::{Deserialize, Serialize};
 
-use std::io::BufRead;
-use std::io::BufReader;
-use std::process::{Command, Stdio};
-
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct UupdProgress {
     pub level: String,
@@ -46,91 +46,127 @@ impl Plugin for Uupd {
     }
 
     fn version(&self) -> &str {
-        // TODO: implement versioning
-        "0.1.0"
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn icon_name(&self) -> &str {
+        "software-update-available-symbolic"
+    }
+
+    fn author(&self) -> &str {
+        env!("CARGO_PKG_AUTHORS")
+    }
+
+    fn homepage(&self) -> &str {
+        env!("CARGO_PKG_HOMEPAGE")
+    }
+
+    /// uupd runs with pkexec
+    fn privileges(&self) -> Privileges {
+        Privileges::Administrator
+    }
+
+    /// uupd can only update everything at once, without listing what it will update
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            check: false,
+            selective_update: false,
+            rollback: false,
+            changelog: false,
+            background_check: false,
+        }
+    }
+
+    /// Uupd conflicts with all other plugins, since it updates everything they do
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        plugin_name != self.name()
+    }
+
+    /// uupd has to be installed
+    fn is_available(&self) -> Availability {
+        Availability::requires("uupd")
     }
 
-    /// Uupd conflicts with all other plugins
-    fn conflicts(&self, _plugin_name: &str) -> bool {
-        true
+    /// uupd has no way to list pending updates without applying them
+    fn check(&self) -> Vec<PendingUpdate> {
+        Vec::new()
     }
 
-    /// Run uupd
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool {
+    /// Run uupd. uupd updates everything, so the selection is ignored.
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        _selection: &Selection,
+        _settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
         // This will run uupd and output the progress in json, which we'll use serde to parse
         // the status, do some conversion to make the progress bar more accurate, and bubble
         // that information up to the status closure.
         let cmd = "pkexec uupd --json";
 
-        if let Ok(child_process) = Command::new("sh")
-            .args(["-c", cmd])
-            .stdout(Stdio::piped())
-            .spawn()
-        {
-            let incoming = child_process.stdout.unwrap();
-            let mut previous_overall = 0;
-
-            let _ = &BufReader::new(incoming).lines().for_each(|line| {
-                let data = line.unwrap();
-                println!("Received data: {}", data);
-                let mut finished = false;
-
-                // Unwrap the data from uupd
-                let mut p: UupdProgress = serde_json::from_str(&data).unwrap();
-
-                p.previous_overall = previous_overall;
-
-                // Track the previous progress
-                previous_overall = p.overall;
-
-                let progress = if (p.progress + 1) < p.total {
-                    p.progress + 1
-                } else {
-                    p.progress
-                };
-
-                let mut msg = format!(
-                    "{} {} - {} (step {}/{})...",
-                    p.msg,
-                    p.title,
-                    p.description,
-                    progress,
-                    p.total + 1
-                );
-
-                if p.progress == 100 || (progress == 0 && p.total == 0) {
-                    finished = true;
-                }
-
-                if finished {
-                    msg = "Update complete.".to_string();
-                }
-
-                // Update renovatio with our current progress
-                let mut pgrss = PluginProgress::new(self.name());
-                pgrss.pulse = false;
-
-                // uupd will give us a more detailed step_progress *sometimes*, like when it's downloading
-                // the image, otherwise it'll be 0. We'll use step if we have it, but fall back to the previous overall.
-                if p.step_progress > 0.0 {
-                    pgrss.progress = p.step_progress as u32;
-                } else {
-                    pgrss.progress = p.previous_overall;
-                }
-                pgrss.status = msg;
-
-                // Send the progress back to the main thread and update the UI
-                let _ = tx.send(pgrss);
-            });
-        };
+        let mut previous_overall = 0;
+        let (_stdout, stderr, rc) = execute_streaming(cmd, cancel, |stream, data| {
+            // Pass stderr, and anything that isn't uupd's JSON, straight through to the log
+            let parsed = match stream {
+                Stream::Stdout => serde_json::from_str::<UupdProgress>(data).ok(),
+                Stream::Stderr => None,
+            };
+            let Some(mut p) = parsed else {
+                let _ = tx.send(PluginEvent::log(stream, data));
+                return;
+            };
+
+            let mut finished = false;
+
+            p.previous_overall = previous_overall;
+
+            // Track the previous progress
+            previous_overall = p.overall;
+
+            let progress = if (p.progress + 1) < p.total {
+                p.progress + 1
+            } else {
+                p.progress
+            };
+
+            let mut msg = format!(
+                "{} {} - {} (step {}/{})...",
+                p.msg,
+                p.title,
+                p.description,
+                progress,
+                p.total + 1
+            );
+
+            if p.progress == 100 || (progress == 0 && p.total == 0) {
+                finished = true;
+            }
+
+            if finished {
+                msg = "Update complete.".to_string();
+            }
+
+            // uupd will give us a more detailed step_progress *sometimes*, like when it's downloading
+            // the image, otherwise it'll be 0. We'll use step if we have it, but fall back to the previous overall.
+            let percent = if p.step_progress > 0.0 {
+                p.step_progress as f64
+            } else {
+                p.previous_overall as f64
+            };
+
+            // Send the progress back to the main thread and update the UI
+            let _ = tx.send(PluginEvent::status(&msg));
+            let _ = tx.send(PluginEvent::progress(percent / 100.0));
+        });
         // explicitly drop the tx channel
         drop(tx);
-        true
+        match rc {
+            0 => Ok(()),
+            _ => Err(PluginError::from_command(cmd, &stderr, rc)),
+        }
     }
 }
 
-// Export a function to create an instance of the plugin
-#[unsafe(no_mangle)]
-pub fn create_plugin() -> *mut dyn Plugin {
-    Box::into_raw(Box::new(Uupd))
-}
+// Export the plugin so renovatio can load it
+renovatio::export_plugin!(Uupd);
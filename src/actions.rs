@@ -1,4 +1,4 @@
-use super::PluginMetadata;
+use super::{LoadError, PluginMetadata};
 use crate::modals;
 
 use adw::prelude::*;
@@ -17,18 +17,65 @@ pub fn set_about(app: &adw::Application, window: &adw::ApplicationWindow) {
         .build()]);
 }
 
+/// Set the history action
+pub fn set_history(app: &adw::Application, window: &adw::ApplicationWindow) {
+    app.add_action_entries([ActionEntry::builder("history")
+        .activate(glib::clone!(
+            #[weak]
+            window,
+            move |_app: &adw::Application, _action, _parameter| {
+                modals::history::show(&window);
+            }
+        ))
+        .build()]);
+}
+
+/// Set the rollback action, which goes back to the OS from before the last update
+pub fn set_rollback(
+    app: &adw::Application,
+    window: &adw::ApplicationWindow,
+    plugins: Vec<PluginMetadata>,
+) {
+    app.add_action_entries([ActionEntry::builder("rollback")
+        .activate(glib::clone!(
+            #[weak]
+            window,
+            move |_app: &adw::Application, _action, _parameter| {
+                modals::rollback::show(&window, &plugins);
+            }
+        ))
+        .build()]);
+}
+
 /// Set the preferences action
 pub fn set_preferences(
     app: &adw::Application,
     window: &adw::ApplicationWindow,
     plugins: Vec<PluginMetadata>,
+    failed: Vec<(String, LoadError)>,
 ) {
     app.add_action_entries([ActionEntry::builder("preferences")
         .activate(glib::clone!(
             #[weak]
             window,
             move |_app: &adw::Application, _action, _parameter| {
-                modals::preferences::show(&window, plugins.clone());
+                modals::preferences::show(&window, plugins.clone(), &failed);
+            }
+        ))
+        .build()]);
+}
+
+/// Set the update action, which applies the pending updates like the update button
+pub fn set_update(app: &adw::Application, update_button: &gtk::Button) {
+    app.add_action_entries([ActionEntry::builder("update")
+        .activate(glib::clone!(
+            #[weak]
+            update_button,
+            move |_app: &adw::Application, _action, _parameter| {
+                // Don't start another update while one is running
+                if update_button.is_sensitive() {
+                    update_button.emit_clicked();
+                }
             }
         ))
         .build()]);
@@ -0,0 +1,175 @@
+//! Checking for updates in the background.
+//!
+//! `renovatio --background` runs as a GApplication service, without a window. Every few
+//! hours it asks the enabled plugins for their pending updates and, if there are any, sends
+//! a notification offering to update now or later. Plugins that would ask for a password to
+//! check are left out, see `PluginMetadata::can_check_in_background`. Opening Renovatio while
+//! the service is running opens the window in the service. It also applies the updates
+//! unattended, see the `unattended` module.
+
+use crate::{config, unattended, utils};
+
+use adw::prelude::*;
+use gio::ActionEntry;
+use renovatio::PendingUpdate;
+use renovatio::engine::EngineEvent;
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait after starting before the first check, so it doesn't slow down logging in
+const FIRST_CHECK_DELAY: Duration = Duration::from_secs(60);
+
+/// The id of the notification, so a newer one replaces it
+const NOTIFICATION_ID: &str = "updates-available";
+
+/// Keep the application running without a window, checking for updates periodically
+pub fn start(app: &adw::Application) {
+    // The service runs until it's killed, with or without windows
+    std::mem::forget(app.hold());
+
+    app.add_action_entries([
+        ActionEntry::builder("update-now")
+            .activate(|app: &adw::Application, _action, _parameter| {
+                app.withdraw_notification(NOTIFICATION_ID);
+                app.activate();
+                app.activate_action("update", None);
+            })
+            .build(),
+        ActionEntry::builder("later")
+            .activate(|app: &adw::Application, _action, _parameter| {
+                app.withdraw_notification(NOTIFICATION_ID);
+            })
+            .build(),
+    ]);
+
+    schedule_check(app, FIRST_CHECK_DELAY);
+
+    // Apply the updates on schedule, if the user wants them to be
+    unattended::start(app);
+}
+
+/// Check for updates after `delay`, then again after the interval in the preferences
+fn schedule_check(app: &adw::Application, delay: Duration) {
+    glib::timeout_add_local_once(
+        delay,
+        glib::clone!(
+            #[weak]
+            app,
+            move || {
+                check(&app);
+
+                let settings = gio::Settings::new(config::APP_ID);
+                let hours = settings.int("check-interval").max(1) as u64;
+                schedule_check(&app, Duration::from_secs(hours * 60 * 60));
+            }
+        ),
+    );
+}
+
+/// Ask each enabled plugin for its pending updates, and notify if there are any
+fn check(app: &adw::Application) {
+    // The window already shows the pending updates
+    if app.active_window().is_some() {
+        return;
+    }
+
+    // Only the plugins that won't ask for a password out of nowhere
+    let (plugins, _failed) = utils::installed_plugins();
+    let enabled = utils::enabled_plugins();
+    let names: Vec<String> = plugins
+        .into_iter()
+        .filter(|metadata| enabled.contains(&metadata.name))
+        .filter(|metadata| metadata.can_check_in_background())
+        .map(|metadata| metadata.name)
+        .collect();
+
+    let (tx, rx) = flume::unbounded::<EngineEvent>();
+    thread::spawn(move || {
+        let mut engine = utils::load_engine(&utils::plugin_paths(&names), tx);
+        engine.check();
+    });
+
+    glib::spawn_future_local(glib::clone!(
+        #[weak]
+        app,
+        async move {
+            let mut pending: Vec<(String, Vec<PendingUpdate>)> = Vec::new();
+            while let Ok(event) = rx.recv_async().await {
+                if let EngineEvent::Checked { plugin, updates } = event {
+                    pending.push((plugin, updates));
+                }
+            }
+            notify(&app, &pending);
+        }
+    ));
+}
+
+/// Notify that updates are available, i.e. "5 updates available (3 flatpak, 2 brew)"
+fn notify(app: &adw::Application, pending: &[(String, Vec<PendingUpdate>)]) {
+    let total: usize = pending.iter().map(|(_, updates)| updates.len()).sum();
+    if total == 0 {
+        app.withdraw_notification(NOTIFICATION_ID);
+        return;
+    }
+
+    let counts = pending
+        .iter()
+        .filter(|(_, updates)| !updates.is_empty())
+        .map(|(name, updates)| format!("{} {}", updates.len(), name))
+        .collect::<Vec<String>>();
+
+    let notification = gio::Notification::new("Updates Available");
+    notification.set_body(Some(&format!(
+        "{} {} available ({})",
+        total,
+        if total == 1 { "update" } else { "updates" },
+        counts.join(", ")
+    )));
+    notification.set_icon(&gio::ThemedIcon::new("software-update-available-symbolic"));
+    notification.add_button("Update Now", "app.update-now");
+    notification.add_button("Later", "app.later");
+    app.send_notification(Some(NOTIFICATION_ID), &notification);
+}
+
+/// Determine if the background checks start when the user logs in
+pub fn is_autostarted() -> bool {
+    autostart_path().exists()
+}
+
+/// Start the background checks when the user logs in, or stop doing so
+pub fn set_autostart(enabled: bool) -> std::io::Result<()> {
+    let path = autostart_path();
+    if !enabled {
+        return match std::fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        };
+    }
+
+    let exe = std::env::current_exe()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(
+        &path,
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=Renovatio\n\
+             Comment=Check for updates in the background\n\
+             Exec={} --background\n\
+             NoDisplay=true\n\
+             X-GNOME-Autostart-enabled=true\n",
+            exe.display()
+        ),
+    )
+}
+
+/// The desktop entry that starts the background checks when the user logs in
+fn autostart_path() -> PathBuf {
+    glib::user_config_dir()
+        .join("autostart")
+        .join(format!("{}.desktop", config::APP_ID))
+}
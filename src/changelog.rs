@@ -0,0 +1,91 @@
+//! The release notes of pending updates.
+//!
+//! Plugins with the `changelog` capability can say what's new in each of their pending
+//! updates, i.e. the packages an OS image changes. Asking can be slow or need the network,
+//! so the answers are cached in `$XDG_CACHE_HOME/renovatio/changelogs.json`, by plugin,
+//! update and versions, which makes showing them again instant and works offline.
+
+use crate::utils;
+
+use renovatio::PendingUpdate;
+use renovatio::engine::EngineEvent;
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+
+/// The number of release notes to keep
+const MAX_ENTRIES: usize = 200;
+
+/// Entry is the release notes of one pending update
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    plugin: String,
+    name: String,
+    current_version: Option<String>,
+    new_version: Option<String>,
+    changelog: String,
+}
+
+impl Entry {
+    /// Determine if these are the release notes of `plugin`'s `update`
+    fn is_for(&self, plugin: &str, update: &PendingUpdate) -> bool {
+        self.plugin == plugin
+            && self.name == update.name
+            && self.current_version == update.current_version
+            && self.new_version == update.new_version
+    }
+}
+
+/// The release notes of `plugin`'s `update`, from the cache if they've been seen before.
+///
+/// This blocks while the plugin is asked, so call it from a worker thread.
+pub fn fetch(plugin: &str, update: &PendingUpdate) -> Option<String> {
+    let mut entries = load();
+    if let Some(entry) = entries.iter().find(|entry| entry.is_for(plugin, update)) {
+        return Some(entry.changelog.clone());
+    }
+
+    // The engine only reports events while checking and updating
+    let (tx, _rx) = flume::unbounded::<EngineEvent>();
+    let engine = utils::load_engine(&utils::plugin_paths(&[plugin.to_string()]), tx);
+    let changelog = engine.changelog(plugin, update)?;
+
+    entries.push(Entry {
+        plugin: plugin.to_string(),
+        name: update.name.clone(),
+        current_version: update.current_version.clone(),
+        new_version: update.new_version.clone(),
+        changelog: changelog.clone(),
+    });
+    if let Err(error) = save(&entries) {
+        eprintln!("Failed to cache the release notes: {}", error);
+    }
+
+    Some(changelog)
+}
+
+/// The file the release notes are cached in
+fn path() -> PathBuf {
+    glib::user_cache_dir()
+        .join("renovatio")
+        .join("changelogs.json")
+}
+
+/// Load the cached release notes, oldest first
+fn load() -> Vec<Entry> {
+    std::fs::read_to_string(path())
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Cache `entries`, forgetting the oldest once there are too many
+fn save(entries: &[Entry]) -> std::io::Result<()> {
+    let path = path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let entries = &entries[entries.len().saturating_sub(MAX_ENTRIES)..];
+    std::fs::write(path, serde_json::to_string(entries)?)
+}
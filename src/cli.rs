@@ -0,0 +1,500 @@
+//! A headless frontend, for running updates over SSH and from scripts.
+//!
+//! It drives the same `UpdateEngine` as the window does, printing its events
+//! to the terminal instead.
+
+use crate::history::{self, Run};
+use crate::utils;
+
+use renovatio::engine::{EngineEvent, EngineState, PluginResult};
+use renovatio::{CancellationToken, PluginEvent, PluginMetadata, Stream};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::thread;
+
+const USAGE: &str = "Usage: renovatio [COMMAND] [OPTIONS]
+
+Opens the window if no command is given.
+
+Commands:
+  list-plugins        List the installed plugins
+  check               List the pending updates
+  update              Apply the pending updates
+  history             List the past updates
+  help                Show this help
+
+Options:
+  --plugins NAME,...  Use these plugins instead of the ones enabled in the preferences
+  -j, --jobs N        Update up to N plugins at once, instead of the number in the preferences
+  -y, --yes           Don't ask for confirmation before updating
+  --search TEXT       Only list the past updates of plugins or items matching TEXT
+  --logs              List the output of the plugins with the past updates
+
+Without a command:
+  --background        Check for updates periodically, notifying when there are some
+";
+
+/// The options shared by the commands
+#[derive(Default)]
+struct Options {
+    /// The names of the plugins to use, if given
+    plugins: Option<Vec<String>>,
+
+    /// Skip the confirmation before updating
+    yes: bool,
+
+    /// The number of plugins to update at once, if given
+    jobs: Option<usize>,
+
+    /// What to search the history for, if given
+    search: Option<String>,
+
+    /// Include the plugins' output in the history
+    logs: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--plugins" => {
+                    let names = args.next().ok_or("--plugins requires a value")?;
+                    options.plugins = Some(names.split(',').map(|s| s.to_string()).collect());
+                }
+                "-y" | "--yes" => options.yes = true,
+                "--search" => {
+                    let search = args.next().ok_or("--search requires a value")?;
+                    options.search = Some(search.clone());
+                }
+                "--logs" => options.logs = true,
+                "-j" | "--jobs" => {
+                    let jobs = args.next().ok_or(format!("{} requires a value", arg))?;
+                    match jobs.parse::<usize>() {
+                        Ok(jobs) if jobs > 0 => options.jobs = Some(jobs),
+                        _ => return Err(format!("Invalid number of jobs: {}", jobs)),
+                    }
+                }
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Run the command given in `args`, if any.
+///
+/// Returns `None` if there is no command, in which case the window should be opened.
+pub fn run(args: &[String]) -> Option<glib::ExitCode> {
+    let command = args.get(1)?;
+
+    if command == "--help" || command == "-h" {
+        print!("{}", USAGE);
+        return Some(glib::ExitCode::SUCCESS);
+    }
+
+    // Leave any other options to the GApplication
+    if command.starts_with('-') {
+        return None;
+    }
+
+    let options = match Options::parse(&args[2..]) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return Some(glib::ExitCode::from(2));
+        }
+    };
+
+    let exit_code = match command.as_str() {
+        "list-plugins" => list_plugins(),
+        "check" => check(&options),
+        "update" => update(&options),
+        "history" => print_history(&options),
+        "help" => {
+            print!("{}", USAGE);
+            glib::ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!("Unknown command: {}\n\n{}", command, USAGE);
+            glib::ExitCode::from(2)
+        }
+    };
+
+    Some(exit_code)
+}
+
+/// Print every installed plugin, and whether it's enabled
+fn list_plugins() -> glib::ExitCode {
+    let (plugins, failed) = utils::installed_plugins();
+    let enabled = utils::enabled_plugins();
+
+    for metadata in plugins {
+        let path = metadata.path.unwrap_or_default();
+        println!(
+            "{} {} {}{}",
+            if enabled.contains(&metadata.name) {
+                "*"
+            } else {
+                " "
+            },
+            metadata.name,
+            metadata.version,
+            if metadata.description.is_empty() {
+                String::new()
+            } else {
+                format!(" - {}", metadata.description)
+            }
+        );
+        println!("    {}", path);
+        if let Some(reason) = metadata.availability.reason() {
+            println!("    Unavailable: {}", reason);
+        }
+    }
+
+    for (path, error) in failed {
+        println!("! {}", path);
+        println!("    {}", error);
+    }
+
+    glib::ExitCode::SUCCESS
+}
+
+/// Print the pending updates of each plugin
+fn check(options: &Options) -> glib::ExitCode {
+    let plugins = match selected_plugins(options) {
+        Ok(plugins) => plugins,
+        Err(error) => {
+            eprintln!("{}", error);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
+    print_pending_updates(plugin_paths(&plugins));
+    glib::ExitCode::SUCCESS
+}
+
+/// Apply the pending updates of each plugin, printing their progress
+fn update(options: &Options) -> glib::ExitCode {
+    let plugins = match selected_plugins(options) {
+        Ok(plugins) => plugins,
+        Err(error) => {
+            eprintln!("{}", error);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+
+    // Plugins that can't check may still have something to do when they run
+    let unchecked: Vec<&str> = plugins
+        .iter()
+        .filter(|metadata| !metadata.capabilities.check)
+        .map(|metadata| metadata.name.as_str())
+        .collect();
+    let plugins = plugin_paths(&plugins);
+
+    if !options.yes {
+        if print_pending_updates(plugins.clone()) == 0 {
+            if unchecked.is_empty() {
+                println!("Everything is up to date.");
+                return glib::ExitCode::SUCCESS;
+            }
+            println!(
+                "{} can't check for updates before running.",
+                unchecked.join(", ")
+            );
+        }
+
+        print!("Apply the updates? [y/N] ");
+        let _ = std::io::stdout().flush();
+
+        let mut answer = String::new();
+        let _ = std::io::stdin().read_line(&mut answer);
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Cancelled.");
+            return glib::ExitCode::FAILURE;
+        }
+    }
+
+    let cancel = CancellationToken::new();
+    cancel_on_interrupt(cancel.clone());
+
+    let (tx, rx) = flume::unbounded::<EngineEvent>();
+    let tx = history::recorder(tx, false);
+    let cancel_worker = cancel.clone();
+    let jobs = options.jobs;
+    thread::spawn(move || {
+        let mut engine = utils::load_engine(&plugins, tx);
+        if let Some(jobs) = jobs {
+            engine.set_max_parallel(jobs);
+        }
+        // A refused run is reported as an event
+        let _ = engine.run(&HashMap::new(), &cancel_worker);
+    });
+
+    let mut success = true;
+    let mut reboot_required = false;
+    let mut results = Vec::new();
+    let mut started = 0;
+    let mut total = 0;
+
+    // Print the progress until every plugin is done
+    for event in rx.iter() {
+        let (plugin, event) = match event {
+            EngineEvent::LoadFailed { path, error } => {
+                eprintln!("Failed to load plugin {}: {}", path, error);
+                success = false;
+                continue;
+            }
+            EngineEvent::Refused(error) => {
+                eprintln!("Can't start the update: {}", error);
+                return glib::ExitCode::FAILURE;
+            }
+            EngineEvent::StateChanged(EngineState::Running { total: count, .. }) => {
+                total = count;
+                continue;
+            }
+            EngineEvent::PluginFinished(result) => {
+                success &= result.success;
+                reboot_required |= result.reboot_required;
+                results.push(result);
+                continue;
+            }
+            EngineEvent::Plugin { plugin, event } => (plugin, event),
+            _ => continue,
+        };
+
+        // Plugins can update in parallel, so say whose output each line is
+        match event {
+            PluginEvent::Started => {
+                started += 1;
+                println!("Updating {} ({}/{})...", plugin, started, total);
+            }
+            PluginEvent::Status { message } => println!("[{}] {}", plugin, message),
+            PluginEvent::Log {
+                stream: Stream::Stdout,
+                line,
+            } => println!("    {}: {}", plugin, line),
+            PluginEvent::Log {
+                stream: Stream::Stderr,
+                line,
+            } => eprintln!("    {}: {}", plugin, line),
+            PluginEvent::RebootRequired { reason } => println!("[{}] {}", plugin, reason),
+            _ => {}
+        }
+    }
+
+    println!();
+    print_results(&results);
+
+    if reboot_required {
+        println!("A reboot is required to finish updating.");
+    }
+
+    if cancel.is_cancelled() {
+        eprintln!("Updates cancelled.");
+        glib::ExitCode::FAILURE
+    } else if success {
+        println!("Updates complete!");
+        glib::ExitCode::SUCCESS
+    } else {
+        eprintln!("Some updates failed.");
+        glib::ExitCode::FAILURE
+    }
+}
+
+/// Cancel the update on the first Ctrl+C, and quit on the second.
+///
+/// Commands run in their own process group, so they don't see the terminal's Ctrl+C.
+fn cancel_on_interrupt(cancel: CancellationToken) {
+    thread::spawn(move || {
+        let context = glib::MainContext::new();
+        let source = glib::unix_signal_source_new(
+            libc::SIGINT,
+            Some("renovatio-interrupt"),
+            glib::Priority::DEFAULT,
+            move || {
+                if cancel.is_cancelled() {
+                    std::process::exit(130);
+                }
+
+                eprintln!("Cancelling, press Ctrl+C again to quit...");
+                cancel.cancel();
+                glib::ControlFlow::Continue
+            },
+        );
+        source.attach(Some(&context));
+
+        loop {
+            context.iteration(true);
+        }
+    });
+}
+
+/// Get the plugins named in the options, or the enabled plugins
+fn selected_plugins(options: &Options) -> Result<Vec<PluginMetadata>, String> {
+    let (plugins, _failed) = utils::installed_plugins();
+    let find = |name: &String| plugins.iter().find(|metadata| &metadata.name == name);
+
+    let Some(names) = &options.plugins else {
+        // Enabled plugins that were uninstalled are left out
+        return Ok(utils::enabled_plugins()
+            .iter()
+            .filter_map(|name| {
+                let metadata = find(name);
+                if metadata.is_none() {
+                    eprintln!("Plugin not found: {}", name);
+                }
+                metadata.cloned()
+            })
+            .collect());
+    };
+
+    names
+        .iter()
+        .map(|name| {
+            find(name)
+                .cloned()
+                .ok_or(format!("Plugin not found: {}", name))
+        })
+        .collect()
+}
+
+/// The paths to load `plugins` from
+fn plugin_paths(plugins: &[PluginMetadata]) -> Vec<String> {
+    plugins
+        .iter()
+        .filter_map(|metadata| metadata.path.clone())
+        .collect()
+}
+
+/// Print the pending updates of each plugin, returning how many there are
+fn print_pending_updates(plugins: Vec<String>) -> usize {
+    let (tx, rx) = flume::unbounded::<EngineEvent>();
+    thread::spawn(move || {
+        let mut engine = utils::load_engine(&plugins, tx);
+        engine.check();
+    });
+
+    let mut total = 0;
+    for event in rx.iter() {
+        let EngineEvent::Checked {
+            plugin: name,
+            updates,
+        } = event
+        else {
+            continue;
+        };
+        total += updates.len();
+
+        println!("{}: {} pending", name, updates.len());
+        for update in updates {
+            let mut line = format!("    {}", update.name);
+            match (&update.current_version, &update.new_version) {
+                (Some(current), Some(new)) => line = format!("{} {} → {}", line, current, new),
+                (None, Some(new)) => line = format!("{} {}", line, new),
+                _ => {}
+            }
+            if let Some(size) = &update.size {
+                line = format!("{} ({})", line, size);
+            }
+            println!("{}", line);
+        }
+    }
+
+    total
+}
+
+/// Print the past runs, oldest first, limited to the plugins and search in the options
+fn print_history(options: &Options) -> glib::ExitCode {
+    let runs: Vec<Run> = history::load()
+        .into_iter()
+        .filter(|run| {
+            options
+                .search
+                .as_deref()
+                .is_none_or(|search| run.matches(search))
+        })
+        .collect();
+
+    if runs.is_empty() {
+        println!("No updates recorded in {}", history::path().display());
+        return glib::ExitCode::SUCCESS;
+    }
+
+    for mut run in runs {
+        if let Some(names) = &options.plugins {
+            run.results.retain(|result| names.contains(&result.name));
+            run.logs.retain(|log| names.contains(&log.plugin));
+            if run.results.is_empty() {
+                continue;
+            }
+        }
+
+        let started = run
+            .started()
+            .and_then(|started| started.format("%Y-%m-%d %H:%M").ok())
+            .unwrap_or_default();
+        println!(
+            "{}{}: {}",
+            started,
+            if run.unattended { " (unattended)" } else { "" },
+            run.summary()
+        );
+
+        print_results(&run.results);
+
+        if options.logs {
+            for log in &run.logs {
+                println!("    {}: {}", log.plugin, log.line);
+            }
+        }
+        println!();
+    }
+
+    glib::ExitCode::SUCCESS
+}
+
+/// Print what each plugin did
+fn print_results(results: &[PluginResult]) {
+    for result in results {
+        println!(
+            "{}: {} in {}",
+            result.name,
+            if result.cancelled {
+                "cancelled"
+            } else if result.success {
+                "succeeded"
+            } else {
+                "failed"
+            },
+            utils::format_duration(result.elapsed)
+        );
+
+        for item in result.upgraded() {
+            match (&item.old_version, &item.new_version) {
+                (Some(old), Some(new)) => println!("    {} {} → {}", item.name, old, new),
+                _ => println!("    {}", item.name),
+            }
+        }
+        for item in result.failed() {
+            println!("    {} failed", item.name);
+            for line in item.error.as_deref().unwrap_or("").lines() {
+                println!("        {}", line);
+            }
+        }
+        if let Some(failure) = &result.failure {
+            println!("    {}", failure);
+        }
+        if result.items.is_empty()
+            && let Some(error) = &result.error
+        {
+            for line in error.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+}
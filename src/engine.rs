@@ -0,0 +1,1031 @@
+//! Orchestrates checking and updating plugins, independently of any frontend.
+//!
+//! The `UpdateEngine` runs on a worker thread and reports everything it does as
+//! `EngineEvent`s, so the window and the command line can follow along the same way.
+
+use crate::loader::{self, LoadedPlugin, panic_message};
+use crate::order;
+use crate::settings::PluginSettings;
+use crate::{
+    Availability, CancellationToken, ItemResult, Outcome, PendingUpdate, Plugin, PluginError,
+    PluginEvent, RollbackTarget, Selection, Stream,
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// EngineState describes what the engine is doing
+#[derive(Clone, Debug)]
+pub enum EngineState {
+    /// Nothing is running
+    Idle,
+
+    /// The plugins are checking for pending updates
+    Checking,
+
+    /// Plugins are updating
+    Running {
+        /// The names of the plugins that are updating
+        active: Vec<String>,
+
+        /// The number of plugins that have finished updating
+        finished: usize,
+
+        /// The number of plugins in the run
+        total: usize,
+    },
+
+    /// Every plugin has finished updating
+    Finished(Vec<PluginResult>),
+}
+
+/// PluginResult describes how a plugin's update went
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PluginResult {
+    /// The name of the plugin
+    pub name: String,
+
+    /// Whether the update was successful
+    pub success: bool,
+
+    /// Whether the update was cancelled before it finished, or before it started
+    pub cancelled: bool,
+
+    /// Whether the plugin reported that a reboot is required
+    pub reboot_required: bool,
+
+    /// The outcome of each item the plugin updated
+    pub items: Vec<ItemResult>,
+
+    /// Why the update failed, if it did
+    pub failure: Option<PluginError>,
+
+    /// The error output the plugin reported, if it failed
+    pub error: Option<String>,
+
+    /// How long the update took
+    pub elapsed: Duration,
+}
+
+impl PluginResult {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            success: false,
+            cancelled: false,
+            reboot_required: false,
+            items: Vec::new(),
+            failure: None,
+            error: None,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// How the update ended
+    pub fn outcome(&self) -> Outcome {
+        if self.cancelled {
+            Outcome::Cancelled
+        } else if self.success {
+            Outcome::Succeeded
+        } else {
+            Outcome::Failed {
+                error: self
+                    .failure
+                    .as_ref()
+                    .map(|failure| failure.to_string())
+                    .or(self.error.clone()),
+            }
+        }
+    }
+
+    /// The items that were updated successfully
+    pub fn upgraded(&self) -> impl Iterator<Item = &ItemResult> {
+        self.items.iter().filter(|item| item.success)
+    }
+
+    /// The items that failed to update
+    pub fn failed(&self) -> impl Iterator<Item = &ItemResult> {
+        self.items.iter().filter(|item| !item.success)
+    }
+}
+
+/// EngineEvent is sent by the engine to report what it's doing
+#[derive(Clone, Debug)]
+pub enum EngineEvent {
+    /// The engine's state changed
+    StateChanged(EngineState),
+
+    /// A plugin couldn't be loaded
+    LoadFailed { path: String, error: String },
+
+    /// A plugin finished checking for pending updates
+    Checked {
+        plugin: String,
+        updates: Vec<PendingUpdate>,
+    },
+
+    /// A plugin reported what it's doing. Every plugin in a run starts with
+    /// `PluginEvent::Started` and ends with `PluginEvent::Finished`.
+    Plugin { plugin: String, event: PluginEvent },
+
+    /// A plugin finished updating, right after its `PluginEvent::Finished`
+    PluginFinished(PluginResult),
+
+    /// The engine refused to start a run
+    Refused(EngineError),
+}
+
+/// EngineError describes why the engine refused to start a run
+#[derive(Clone, Debug)]
+pub enum EngineError {
+    /// Two of the plugins conflict, so they can't run together
+    Conflict { plugin: String, other: String },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Conflict { plugin, other } => write!(
+                f,
+                "{} conflicts with {}, so they can't run together",
+                plugin, other
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// Determine if two plugins conflict, whichever of them declares it
+pub fn plugins_conflict(a: &dyn Plugin, b: &dyn Plugin) -> bool {
+    a.conflicts(b.name()) || b.conflicts(a.name())
+}
+
+/// Determine if two plugins change the same system state, and so can't update at the same time
+pub fn shares_resources(a: &[String], b: &[String]) -> bool {
+    a.iter()
+        .any(|resource| resource == "*" || b.contains(resource))
+        || b.iter().any(|resource| resource == "*")
+}
+
+/// Find each pair of plugins that conflict
+pub fn find_conflicts(plugins: &[LoadedPlugin]) -> Vec<(String, String)> {
+    let mut conflicts = Vec::new();
+
+    for (index, plugin) in plugins.iter().enumerate() {
+        for other in &plugins[index + 1..] {
+            if plugins_conflict(&**plugin, &**other) {
+                conflicts.push((plugin.name().to_string(), other.name().to_string()));
+            }
+        }
+    }
+
+    conflicts
+}
+
+/// UpdateEngine checks and updates a set of plugins in their run order, updating plugins
+/// that don't share resources at the same time
+pub struct UpdateEngine {
+    plugins: Vec<LoadedPlugin>,
+
+    /// The indices of the plugins each plugin waits for before updating
+    dependencies: Vec<Vec<usize>>,
+
+    /// The number of plugins that may update at the same time
+    max_parallel: usize,
+
+    state: EngineState,
+    events: flume::Sender<EngineEvent>,
+}
+
+impl UpdateEngine {
+    /// Create an engine for the given plugins, reporting to `events`.
+    /// The plugins are sorted by their declared run order.
+    pub fn new(plugins: Vec<LoadedPlugin>, events: flume::Sender<EngineEvent>) -> Self {
+        let mut engine = Self {
+            plugins,
+            dependencies: Vec::new(),
+            max_parallel: 1,
+            state: EngineState::Idle,
+            events,
+        };
+        engine.sort(&[]);
+        engine
+    }
+
+    /// Create an engine for the plugins at `paths`. Plugins that fail to load are
+    /// reported as `EngineEvent::LoadFailed` and left out.
+    pub fn load(paths: &[String], events: flume::Sender<EngineEvent>) -> Self {
+        let mut plugins = Vec::new();
+
+        for path in paths {
+            match loader::load_plugin(path) {
+                Ok(plugin) => match plugin.is_available() {
+                    Availability::Available => plugins.push(plugin),
+                    // Leave out plugins that can't run here, rather than have them fail
+                    Availability::Unavailable(reason) => {
+                        let _ = events.send(EngineEvent::LoadFailed {
+                            path: path.clone(),
+                            error: format!("{} is unavailable: {}", plugin.name(), reason),
+                        });
+                    }
+                },
+                Err(error) => {
+                    let _ = events.send(EngineEvent::LoadFailed {
+                        path: path.clone(),
+                        error: error.to_string(),
+                    });
+                }
+            }
+        }
+
+        Self::new(plugins, events)
+    }
+
+    /// Sort the plugins by their declared run order, letting `user_order` override it.
+    /// See `order::sort`.
+    pub fn sort(&mut self, user_order: &[String]) {
+        let run_orders: Vec<_> = self.plugins.iter().map(|p| p.run_order()).collect();
+        let plugins: Vec<_> = self
+            .plugins
+            .iter()
+            .zip(&run_orders)
+            .map(|(plugin, run_order)| (plugin.name(), run_order))
+            .collect();
+        let sorted = order::sort(&plugins, user_order);
+
+        // Work out what each plugin waits for, by its new position
+        let sorted_plugins: Vec<_> = sorted.iter().map(|&index| plugins[index]).collect();
+        self.dependencies = order::dependencies(&sorted_plugins, user_order);
+
+        let mut plugins: Vec<Option<LoadedPlugin>> = self.plugins.drain(..).map(Some).collect();
+        self.plugins = sorted
+            .into_iter()
+            .filter_map(|index| plugins[index].take())
+            .collect();
+    }
+
+    /// Update at most `max` plugins at the same time. Defaults to 1, one after the other.
+    pub fn set_max_parallel(&mut self, max: usize) {
+        self.max_parallel = max.max(1);
+    }
+
+    /// The current state of the engine
+    pub fn state(&self) -> &EngineState {
+        &self.state
+    }
+
+    /// The names of the plugins, in the order they run
+    pub fn plugin_names(&self) -> Vec<String> {
+        self.plugins.iter().map(|p| p.name().to_string()).collect()
+    }
+
+    /// Find each pair of plugins that conflict, and so can't run together
+    pub fn conflicts(&self) -> Vec<(String, String)> {
+        find_conflicts(&self.plugins)
+    }
+
+    /// Ask each plugin for its pending updates, reporting them as they're found
+    pub fn check(&mut self) -> Vec<(String, Vec<PendingUpdate>)> {
+        self.set_state(EngineState::Checking);
+
+        let mut pending = Vec::new();
+        for plugin in &self.plugins {
+            // A plugin that panics has nothing to report, but doesn't stop the others
+            let updates = panic::catch_unwind(AssertUnwindSafe(|| plugin.check())).unwrap_or_else(
+                |payload| {
+                    eprintln!(
+                        "[{}]: Check failed: {}",
+                        plugin.name(),
+                        panic_message(payload.as_ref())
+                    );
+                    Vec::new()
+                },
+            );
+            let _ = self.events.send(EngineEvent::Checked {
+                plugin: plugin.name().to_string(),
+                updates: updates.clone(),
+            });
+            pending.push((plugin.name().to_string(), updates));
+        }
+
+        self.set_state(EngineState::Idle);
+        pending
+    }
+
+    /// Ask `plugin` what's new in one of its pending updates
+    pub fn changelog(&self, plugin: &str, update: &PendingUpdate) -> Option<String> {
+        let plugin = self.plugins.iter().find(|loaded| loaded.name() == plugin)?;
+
+        // A plugin that panics has nothing to say, but doesn't take the frontend down with it
+        panic::catch_unwind(AssertUnwindSafe(|| plugin.changelog(update))).unwrap_or_else(
+            |payload| {
+                eprintln!(
+                    "[{}]: Changelog failed: {}",
+                    plugin.name(),
+                    panic_message(payload.as_ref())
+                );
+                None
+            },
+        )
+    }
+
+    /// Ask `plugin` what rolling back would go back to
+    pub fn rollback_target(&self, plugin: &str) -> Option<RollbackTarget> {
+        let plugin = self.plugins.iter().find(|loaded| loaded.name() == plugin)?;
+        if !plugin.capabilities().rollback {
+            return None;
+        }
+
+        panic::catch_unwind(AssertUnwindSafe(|| plugin.rollback_target())).unwrap_or_else(
+            |payload| {
+                eprintln!(
+                    "[{}]: Finding the rollback failed: {}",
+                    plugin.name(),
+                    panic_message(payload.as_ref())
+                );
+                None
+            },
+        )
+    }
+
+    /// Roll `plugin` back to what was installed before its last update, reporting it like
+    /// a run of that plugin alone. Returns `None` if the plugin isn't loaded.
+    pub fn rollback(&mut self, plugin: &str) -> Option<PluginResult> {
+        let plugin = self.plugins.iter().find(|loaded| loaded.name() == plugin)?;
+
+        let _ = self.events.send(EngineEvent::Plugin {
+            plugin: plugin.name().to_string(),
+            event: PluginEvent::Started,
+        });
+        let result = run_action(
+            plugin,
+            &CancellationToken::new(),
+            &self.events,
+            |tx, settings| {
+                if plugin.capabilities().rollback {
+                    plugin.rollback(tx, settings)
+                } else {
+                    Err(PluginError::Failed(format!(
+                        "{} can't roll back",
+                        plugin.name()
+                    )))
+                }
+            },
+        );
+        send_finished(&self.events, &result);
+
+        self.set_state(EngineState::Finished(vec![result.clone()]));
+        Some(result)
+    }
+
+    /// Update each plugin, applying only the selected updates. Plugins without a selection
+    /// apply every update.
+    ///
+    /// Plugins start in run order, once the plugins they run after have finished, as long as
+    /// fewer than the maximum are updating and none of them share a resource.
+    ///
+    /// Once `cancel` is cancelled the running plugins are asked to stop, and the plugins
+    /// that haven't started are reported as cancelled without running.
+    ///
+    /// Nothing is run if any of the plugins conflict. That's reported as `EngineEvent::Refused`.
+    pub fn run(
+        &mut self,
+        selections: &HashMap<String, Selection>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<PluginResult>, EngineError> {
+        if let Some((plugin, other)) = self.conflicts().into_iter().next() {
+            let error = EngineError::Conflict { plugin, other };
+            let _ = self.events.send(EngineEvent::Refused(error.clone()));
+            return Err(error);
+        }
+
+        let total = self.plugins.len();
+        let plugins = &self.plugins;
+        let dependencies = &self.dependencies;
+        let events = &self.events;
+        let max_parallel = self.max_parallel;
+        let resources: Vec<Vec<String>> = plugins.iter().map(|p| p.resources()).collect();
+        let mut results: Vec<Option<PluginResult>> = vec![None; total];
+
+        thread::scope(|scope| {
+            let (done_tx, done_rx) = flume::unbounded::<(usize, PluginResult)>();
+            let mut waiting: Vec<usize> = (0..total).collect();
+            let mut running: Vec<usize> = Vec::new();
+
+            loop {
+                // Start as many of the waiting plugins as we can, in run order
+                while !cancel.is_cancelled() && running.len() < max_parallel {
+                    let ready = waiting.iter().position(|&index| {
+                        dependencies[index]
+                            .iter()
+                            .all(|&dependency| results[dependency].is_some())
+                            && running.iter().all(|&other| {
+                                !shares_resources(&resources[index], &resources[other])
+                            })
+                    });
+
+                    // Plugins that wait on each other in a cycle start in run order
+                    let ready = ready.or((running.is_empty() && !waiting.is_empty()).then_some(0));
+                    let Some(position) = ready else {
+                        break;
+                    };
+
+                    let index = waiting.remove(position);
+                    running.push(index);
+
+                    let plugin = &plugins[index];
+                    send_running(events, plugins, &running, &results);
+                    let _ = events.send(EngineEvent::Plugin {
+                        plugin: plugin.name().to_string(),
+                        event: PluginEvent::Started,
+                    });
+
+                    let selection = selections.get(plugin.name()).cloned().unwrap_or_default();
+                    let done_tx = done_tx.clone();
+                    scope.spawn(move || {
+                        let result = run_plugin(plugin, &selection, cancel, events);
+                        let _ = done_tx.send((index, result));
+                    });
+                }
+
+                if running.is_empty() {
+                    break;
+                }
+
+                // Wait for a plugin to finish, then see what can start
+                let Ok((index, result)) = done_rx.recv() else {
+                    break;
+                };
+                running.retain(|&other| other != index);
+                send_finished(events, &result);
+                results[index] = Some(result);
+
+                if !running.is_empty() {
+                    send_running(events, plugins, &running, &results);
+                }
+            }
+
+            // The plugins that never started were cancelled
+            for index in waiting {
+                let mut result = PluginResult::new(plugins[index].name());
+                result.cancelled = true;
+                send_finished(events, &result);
+                results[index] = Some(result);
+            }
+        });
+
+        let results: Vec<PluginResult> = results.into_iter().flatten().collect();
+        self.set_state(EngineState::Finished(results.clone()));
+        Ok(results)
+    }
+
+    fn set_state(&mut self, state: EngineState) {
+        self.state = state;
+        let _ = self
+            .events
+            .send(EngineEvent::StateChanged(self.state.clone()));
+    }
+}
+
+/// Report which plugins are updating
+fn send_running(
+    events: &flume::Sender<EngineEvent>,
+    plugins: &[LoadedPlugin],
+    running: &[usize],
+    results: &[Option<PluginResult>],
+) {
+    let _ = events.send(EngineEvent::StateChanged(EngineState::Running {
+        active: running
+            .iter()
+            .map(|&index| plugins[index].name().to_string())
+            .collect(),
+        finished: results.iter().flatten().count(),
+        total: plugins.len(),
+    }));
+}
+
+/// Report that a plugin finished, first as its last `PluginEvent` and then with its result
+fn send_finished(events: &flume::Sender<EngineEvent>, result: &PluginResult) {
+    let _ = events.send(EngineEvent::Plugin {
+        plugin: result.name.clone(),
+        event: PluginEvent::Finished {
+            outcome: result.outcome(),
+        },
+    });
+    let _ = events.send(EngineEvent::PluginFinished(result.clone()));
+}
+
+/// Run a single plugin's update, forwarding its events as engine events
+fn run_plugin(
+    plugin: &LoadedPlugin,
+    selection: &Selection,
+    cancel: &CancellationToken,
+    events: &flume::Sender<EngineEvent>,
+) -> PluginResult {
+    run_action(plugin, cancel, events, |tx, settings| {
+        plugin.update(tx, selection, settings, cancel)
+    })
+}
+
+/// Run `action` on a single plugin, i.e. its update, forwarding its events as engine events
+fn run_action(
+    plugin: &LoadedPlugin,
+    cancel: &CancellationToken,
+    events: &flume::Sender<EngineEvent>,
+    action: impl FnOnce(flume::Sender<PluginEvent>, &PluginSettings) -> Result<(), PluginError>,
+) -> PluginResult {
+    let started = Instant::now();
+    let mut result = PluginResult::new(plugin.name());
+
+    let (tx, rx) = flume::unbounded::<PluginEvent>();
+
+    // Forward the events until the plugin drops its sender, collecting the outcome
+    let forward_events = events.clone();
+    let mut forward_result = result.clone();
+    let forwarder = thread::spawn(move || {
+        // The items that have started, to know their versions once they finish
+        let mut steps: HashMap<String, PendingUpdate> = HashMap::new();
+
+        for event in rx.iter() {
+            match &event {
+                // The engine reports when plugins start and finish
+                PluginEvent::Started | PluginEvent::Finished { .. } => continue,
+                PluginEvent::StepStarted { item } => {
+                    steps.insert(item.name.clone(), item.clone());
+                }
+                PluginEvent::StepFinished { item, outcome } => {
+                    let update = steps
+                        .remove(item)
+                        .unwrap_or_else(|| PendingUpdate::new(item));
+                    forward_result
+                        .items
+                        .push(ItemResult::from_update(&update, outcome));
+                }
+                PluginEvent::Log {
+                    stream: Stream::Stderr,
+                    line,
+                } if !line.trim().is_empty() => {
+                    let error = forward_result.error.get_or_insert_with(String::new);
+                    error.push_str(line.trim_end());
+                    error.push('\n');
+                }
+                PluginEvent::RebootRequired { .. } => forward_result.reboot_required = true,
+                _ => {}
+            }
+            let _ = forward_events.send(EngineEvent::Plugin {
+                plugin: forward_result.name.clone(),
+                event,
+            });
+        }
+        forward_result
+    });
+
+    // The settings the user saved for the plugin, with defaults for the rest
+    let settings = PluginSettings::load(plugin.name(), &plugin.settings());
+
+    // Run the blocking update. A plugin that panics fails on its own, rather than taking
+    // the rest of the run down with it. Shared library plugins catch their own panics, see
+    // `loader::PanicGuard`.
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| action(tx, &settings)))
+        .unwrap_or_else(|payload| Err(PluginError::Panicked(panic_message(payload.as_ref()))));
+
+    // Wait for the remaining events, so they're reported before the plugin finishes
+    if let Ok(forwarded) = forwarder.join() {
+        result = forwarded;
+    }
+
+    // An update that was cancelled didn't succeed, even if the plugin stopped cleanly
+    result.cancelled = cancel.is_cancelled();
+    result.success = outcome.is_ok() && !result.cancelled;
+    result.elapsed = started.elapsed();
+    if result.success || result.cancelled {
+        result.error = None;
+    } else {
+        result.failure = outcome.err();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RunOrder;
+
+    use std::sync::{Arc, Condvar, Mutex};
+
+    /// How long plugins wait for a meeting that should happen, which is only reached if the
+    /// engine is broken
+    const EXPECTED: Duration = Duration::from_secs(10);
+
+    /// How long plugins wait for a meeting that shouldn't happen, which is long enough for
+    /// plugins that were wrongly started together to meet
+    const UNEXPECTED: Duration = Duration::from_millis(100);
+
+    /// Meeting is a place plugins wait for each other while they update, to tell whether they
+    /// updated at the same time without relying on how long they take
+    struct Meeting {
+        name: &'static str,
+
+        /// How many plugins have to be there at once for them to meet
+        size: usize,
+
+        /// How long each plugin waits for the others
+        patience: Duration,
+
+        /// The number of plugins waiting, and whether `size` of them were ever there at once
+        state: Mutex<(usize, bool)>,
+        changed: Condvar,
+    }
+
+    impl Meeting {
+        fn new(name: &'static str, size: usize, patience: Duration) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                size,
+                patience,
+                state: Mutex::new((0, false)),
+                changed: Condvar::new(),
+            })
+        }
+
+        /// Wait for the others, returning whether the meeting happened
+        fn attend(&self) -> bool {
+            let mut state = self.state.lock().unwrap();
+            state.0 += 1;
+            if state.0 >= self.size {
+                state.1 = true;
+                self.changed.notify_all();
+            }
+
+            let (mut state, _) = self
+                .changed
+                .wait_timeout_while(state, self.patience, |(_, met)| !*met)
+                .unwrap();
+            state.0 -= 1;
+            state.1
+        }
+    }
+
+    /// A plugin that updates one item, failing, panicking or cancelling the run if asked
+    struct Fake {
+        name: String,
+        resources: Vec<String>,
+        run_order: RunOrder,
+        fails: bool,
+        panics: bool,
+        cancels: bool,
+
+        /// The meetings to attend while updating, in order
+        meetings: Vec<Arc<Meeting>>,
+
+        /// When each plugin started and finished updating, and who it met, i.e. "a started"
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Fake {
+        fn new(name: &str) -> Self {
+            Self {
+                name: name.to_string(),
+                resources: vec![name.to_string()],
+                run_order: RunOrder::default(),
+                fails: false,
+                panics: false,
+                cancels: false,
+                meetings: Vec::new(),
+                log: Arc::default(),
+            }
+        }
+
+        fn load(self) -> LoadedPlugin {
+            LoadedPlugin::new(Box::new(self))
+        }
+    }
+
+    impl Plugin for Fake {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn description(&self) -> &str {
+            "A fake plugin"
+        }
+
+        fn version(&self) -> &str {
+            "1.0"
+        }
+
+        fn conflicts(&self, _plugin_name: &str) -> bool {
+            false
+        }
+
+        fn run_order(&self) -> RunOrder {
+            self.run_order.clone()
+        }
+
+        fn resources(&self) -> Vec<String> {
+            self.resources.clone()
+        }
+
+        fn check(&self) -> Vec<PendingUpdate> {
+            vec![PendingUpdate::new("item")]
+        }
+
+        extern "Rust" fn update(
+            &self,
+            tx: flume::Sender<PluginEvent>,
+            _selection: &Selection,
+            _settings: &PluginSettings,
+            cancel: &CancellationToken,
+        ) -> Result<(), PluginError> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} started", self.name));
+            let _ = tx.send(PluginEvent::status("Updating"));
+            let _ = tx.send(PluginEvent::StepStarted {
+                item: PendingUpdate::new("item"),
+            });
+
+            for meeting in &self.meetings {
+                if meeting.attend() {
+                    self.log
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} met {}", self.name, meeting.name));
+                }
+            }
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} finished", self.name));
+
+            if self.cancels {
+                cancel.cancel();
+            }
+            if self.panics {
+                panic!("the fake plugin panicked");
+            }
+            let _ = tx.send(PluginEvent::StepFinished {
+                item: "item".to_string(),
+                outcome: Outcome::from_success(!self.fails),
+            });
+
+            if self.fails {
+                Err(PluginError::Failed("broken".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Run `plugins` one at a time, returning the results and the events, summarized
+    fn run(
+        plugins: Vec<LoadedPlugin>,
+        cancel: &CancellationToken,
+    ) -> (Vec<PluginResult>, Vec<String>) {
+        let (tx, rx) = flume::unbounded();
+        let mut engine = UpdateEngine::new(plugins, tx);
+        let results = engine.run(&HashMap::new(), cancel).unwrap();
+        (results, rx.drain().filter_map(summarize).collect())
+    }
+
+    fn summarize(event: EngineEvent) -> Option<String> {
+        let outcome = |outcome: &Outcome| match outcome {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed { .. } => "failed",
+            Outcome::Cancelled => "cancelled",
+        };
+
+        Some(match event {
+            EngineEvent::StateChanged(EngineState::Running { active, .. }) => {
+                format!("running {}", active.join(","))
+            }
+            EngineEvent::StateChanged(EngineState::Finished(_)) => "done".to_string(),
+            EngineEvent::Plugin { plugin, event } => match event {
+                PluginEvent::Started => format!("{} started", plugin),
+                PluginEvent::Status { message } => format!("{} status {}", plugin, message),
+                PluginEvent::StepStarted { item } => format!("{} step {}", plugin, item.name),
+                PluginEvent::StepFinished { item, outcome: o } => {
+                    format!("{} step {} {}", plugin, item, outcome(&o))
+                }
+                PluginEvent::Finished { outcome: o } => format!("{} {}", plugin, outcome(&o)),
+                _ => return None,
+            },
+            EngineEvent::PluginFinished(result) => format!("{} result", result.name),
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn reports_each_plugin_from_start_to_finish() {
+        let (results, events) = run(
+            vec![Fake::new("a").load(), Fake::new("b").load()],
+            &CancellationToken::new(),
+        );
+
+        assert_eq!(
+            events,
+            [
+                "running a",
+                "a started",
+                "a status Updating",
+                "a step item",
+                "a step item succeeded",
+                "a succeeded",
+                "a result",
+                "running b",
+                "b started",
+                "b status Updating",
+                "b step item",
+                "b step item succeeded",
+                "b succeeded",
+                "b result",
+                "done",
+            ]
+        );
+        assert!(results.iter().all(|result| result.success));
+        assert_eq!(results[0].items.len(), 1);
+    }
+
+    #[test]
+    fn failing_plugins_dont_stop_the_others() {
+        let plugins = vec![
+            Fake {
+                fails: true,
+                ..Fake::new("a")
+            }
+            .load(),
+            Fake {
+                panics: true,
+                ..Fake::new("b")
+            }
+            .load(),
+            Fake::new("c").load(),
+        ];
+        let (results, events) = run(plugins, &CancellationToken::new());
+
+        let names: Vec<&str> = results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert!(matches!(results[0].failure, Some(PluginError::Failed(_))));
+        assert!(matches!(results[1].failure, Some(PluginError::Panicked(_))));
+        assert!(results[2].success);
+
+        // Every plugin finishes exactly once, even the one that panicked
+        for name in names {
+            let finished = format!("{} result", name);
+            assert_eq!(events.iter().filter(|event| **event == finished).count(), 1);
+        }
+        assert!(events.contains(&"a failed".to_string()));
+        assert!(events.contains(&"b failed".to_string()));
+    }
+
+    #[test]
+    fn cancelling_skips_the_plugins_that_havent_started() {
+        let plugins = vec![
+            Fake {
+                cancels: true,
+                ..Fake::new("a")
+            }
+            .load(),
+            Fake::new("b").load(),
+        ];
+        let (results, events) = run(plugins, &CancellationToken::new());
+
+        assert!(
+            results
+                .iter()
+                .all(|result| result.cancelled && !result.success)
+        );
+        assert!(events.contains(&"a cancelled".to_string()));
+        assert!(!events.contains(&"b started".to_string()));
+        assert!(events.ends_with(&[
+            "b cancelled".to_string(),
+            "b result".to_string(),
+            "done".to_string()
+        ]));
+    }
+
+    #[test]
+    fn cancelling_before_the_run_starts_nothing() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let (results, events) = run(vec![Fake::new("a").load()], &cancel);
+
+        assert!(results[0].cancelled);
+        assert_eq!(events, ["a cancelled", "a result", "done"]);
+    }
+
+    /// Run `plugins`, up to `max_parallel` at a time, returning when each started and finished
+    fn run_parallel(plugins: Vec<Fake>, max_parallel: usize) -> Vec<String> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let plugins = plugins
+            .into_iter()
+            .map(|fake| {
+                Fake {
+                    log: log.clone(),
+                    ..fake
+                }
+                .load()
+            })
+            .collect();
+
+        let (tx, _rx) = flume::unbounded();
+        let mut engine = UpdateEngine::new(plugins, tx);
+        engine.set_max_parallel(max_parallel);
+        engine
+            .run(&HashMap::new(), &CancellationToken::new())
+            .unwrap();
+
+        log.lock().unwrap().clone()
+    }
+
+    fn position(log: &[String], entry: &str) -> usize {
+        log.iter().position(|other| other == entry).unwrap()
+    }
+
+    #[test]
+    fn plugins_sharing_a_resource_never_overlap() {
+        let os = vec!["os".to_string()];
+        let together = Meeting::new("together", 2, EXPECTED);
+        let os_plugins = Meeting::new("os", 2, UNEXPECTED);
+        let plugins = vec![
+            Fake {
+                resources: os.clone(),
+                meetings: vec![together.clone(), os_plugins.clone()],
+                ..Fake::new("bootc")
+            },
+            Fake {
+                resources: os,
+                meetings: vec![together.clone(), os_plugins],
+                ..Fake::new("rpm-ostree")
+            },
+            Fake {
+                meetings: vec![together],
+                ..Fake::new("flatpak")
+            },
+        ];
+        let log = run_parallel(plugins, 3);
+
+        assert!(
+            !log.iter().any(|entry| entry.ends_with(" met os")),
+            "{:?}",
+            log
+        );
+        // ...while the others update alongside them
+        assert!(
+            log.contains(&"flatpak met together".to_string()),
+            "{:?}",
+            log
+        );
+    }
+
+    #[test]
+    fn at_most_max_parallel_plugins_update_at_once() {
+        let two = Meeting::new("two", 2, EXPECTED);
+        let three = Meeting::new("three", 3, UNEXPECTED);
+        let plugins = ["a", "b", "c", "d"]
+            .map(|name| Fake {
+                meetings: vec![two.clone(), three.clone()],
+                ..Fake::new(name)
+            })
+            .into();
+        let log = run_parallel(plugins, 2);
+
+        assert!(log.contains(&"a met two".to_string()), "{:?}", log);
+        assert!(
+            !log.iter().any(|entry| entry.ends_with(" met three")),
+            "{:?}",
+            log
+        );
+    }
+
+    #[test]
+    fn plugins_wait_for_the_plugins_they_run_after() {
+        let alongside = Meeting::new("alongside", 2, EXPECTED);
+        let plugins = vec![
+            Fake {
+                meetings: vec![alongside.clone()],
+                ..Fake::new("rpm-ostree")
+            },
+            Fake {
+                run_order: RunOrder::after(&["rpm-ostree"]),
+                ..Fake::new("flatpak")
+            },
+            Fake {
+                meetings: vec![alongside],
+                ..Fake::new("brew")
+            },
+        ];
+        let log = run_parallel(plugins, 3);
+
+        assert!(position(&log, "flatpak started") > position(&log, "rpm-ostree finished"));
+        // brew doesn't wait for anything
+        assert!(log.contains(&"brew met alongside".to_string()), "{:?}", log);
+    }
+}
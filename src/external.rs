@@ -0,0 +1,455 @@
+//! Out-of-process plugins.
+//!
+//! An external plugin is any executable in one of the plugin directories. Renovatio talks to
+//! it over the command line and stdout:
+//!
+//! * `<plugin> --describe` prints a JSON object with the plugin's `name`, `description`,
+//!   `version` and, optionally, the names of the plugins it `conflicts` with (`"*"` for all)
+//!   the plugins it should run `before` and `after`, the `resources` it changes, and why it's
+//!   `unavailable` if it can't run on this system. It can also give its `icon_name`, `author`,
+//!   `homepage`, the `privileges` it needs (`"user"` or `"administrator"`) and its
+//!   `capabilities`, which default to `check` and `selective_update`, and the `settings` it
+//!   understands, i.e. `{"key": "casks", "title": "Upgrade casks", "type": "bool", "default": true}`.
+//! * `<plugin> --check` prints a JSON array of `PendingUpdate`s.
+//! * `<plugin> --changelog -- NAME` prints what's new in the pending update `NAME`, as plain
+//!   text, if the plugin has the `changelog` capability.
+//! * `<plugin> --update [-- NAME...]` applies the updates, or only the named ones, printing one
+//!   `PluginEvent` JSON object per line, i.e. `{"event": "status", "message": "Upgrading"}`.
+//!   Lines that aren't JSON are treated as stdout. The update is successful if the plugin
+//!   exits with 0. The values of its settings are passed as a JSON object in the
+//!   `RENOVATIO_SETTINGS` environment variable.
+//! * `<plugin> --rollback-target` prints a JSON `RollbackTarget`, or nothing if there's nothing
+//!   to roll back to, and `<plugin> --rollback` rolls back, printing events like `--update`.
+//!   Both are only used if the plugin has the `rollback` capability.
+//!
+//! Names come after `--`, so one starting with a dash isn't mistaken for an option. Plugins
+//! that don't answer `--describe`, `--check`, `--changelog` or `--rollback-target` in time
+//! are terminated, along with everything they started.
+
+use crate::settings::{PluginSettings, Setting};
+use crate::{
+    Availability, CancellationToken, Capabilities, PendingUpdate, Plugin, PluginError, PluginEvent,
+    Privileges, RollbackTarget, RunOrder, Selection, Stream, stream_command,
+};
+
+use serde::Deserialize;
+
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// How long a plugin has to describe itself or say what it would roll back to
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a plugin has to check for updates or fetch release notes, which need the network
+const CHECK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The output of `<plugin> --describe`
+#[derive(Clone, Debug, Deserialize)]
+struct Description {
+    name: String,
+
+    #[serde(default)]
+    description: String,
+
+    #[serde(default)]
+    version: String,
+
+    #[serde(default)]
+    conflicts: Vec<String>,
+
+    #[serde(flatten)]
+    run_order: RunOrder,
+
+    #[serde(default)]
+    resources: Option<Vec<String>>,
+
+    /// Why the plugin can't run on this system, if it can't
+    #[serde(default)]
+    unavailable: Option<String>,
+
+    #[serde(default)]
+    icon_name: Option<String>,
+
+    #[serde(default)]
+    author: String,
+
+    #[serde(default)]
+    homepage: String,
+
+    #[serde(default)]
+    privileges: Privileges,
+
+    #[serde(default)]
+    capabilities: Option<Capabilities>,
+
+    #[serde(default)]
+    settings: Vec<Setting>,
+}
+
+/// ExternalPlugin runs an executable that speaks the JSON-lines protocol
+pub struct ExternalPlugin {
+    path: String,
+    description: Description,
+}
+
+impl ExternalPlugin {
+    /// Ask the executable at `path` to describe itself
+    pub fn describe(path: &str) -> Result<Self, String> {
+        let output = query(path, &["--describe"], QUERY_TIMEOUT)
+            .map_err(|e| format!("--describe failed: {}", e))?;
+        Self::parse(path, &output)
+    }
+
+    /// The plugin at `path`, given what it printed for `--describe`
+    fn parse(path: &str, output: &str) -> Result<Self, String> {
+        let description: Description = serde_json::from_str(output)
+            .map_err(|e| format!("Invalid --describe output: {}", e))?;
+
+        Ok(Self {
+            path: path.to_string(),
+            description,
+        })
+    }
+
+    /// Run the plugin with `args` and its settings, passing on the events it prints
+    fn run_streaming(
+        &self,
+        args: &[String],
+        tx: &flume::Sender<PluginEvent>,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(args);
+        if let Ok(settings) = serde_json::to_string(settings) {
+            cmd.env("RENOVATIO_SETTINGS", settings);
+        }
+
+        let (_stdout, stderr, rc) = stream_command(cmd, cancel, |stream, line| {
+            let _ = tx.send(event(stream, line));
+        });
+
+        match rc {
+            0 => Ok(()),
+            _ => Err(PluginError::from_command(&self.path, &stderr, rc)),
+        }
+    }
+}
+
+impl Plugin for ExternalPlugin {
+    fn name(&self) -> &str {
+        &self.description.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description.description
+    }
+
+    fn version(&self) -> &str {
+        &self.description.version
+    }
+
+    fn icon_name(&self) -> &str {
+        self.description
+            .icon_name
+            .as_deref()
+            .unwrap_or("application-x-executable-symbolic")
+    }
+
+    fn author(&self) -> &str {
+        &self.description.author
+    }
+
+    fn homepage(&self) -> &str {
+        &self.description.homepage
+    }
+
+    fn privileges(&self) -> Privileges {
+        self.description.privileges
+    }
+
+    /// Every external plugin speaks `--check` and takes the names of the updates to apply
+    fn capabilities(&self) -> Capabilities {
+        self.description.capabilities.unwrap_or(Capabilities {
+            check: true,
+            selective_update: true,
+            rollback: false,
+            changelog: false,
+            background_check: false,
+        })
+    }
+
+    fn settings(&self) -> Vec<Setting> {
+        self.description.settings.clone()
+    }
+
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        self.description
+            .conflicts
+            .iter()
+            .any(|name| name == "*" || name == plugin_name)
+    }
+
+    fn run_order(&self) -> RunOrder {
+        self.description.run_order.clone()
+    }
+
+    fn resources(&self) -> Vec<String> {
+        match &self.description.resources {
+            Some(resources) => resources.clone(),
+            None => vec!["*".to_string()],
+        }
+    }
+
+    fn is_available(&self) -> Availability {
+        match &self.description.unavailable {
+            Some(reason) => Availability::Unavailable(reason.clone()),
+            None => Availability::Available,
+        }
+    }
+
+    fn check(&self) -> Vec<PendingUpdate> {
+        match query(&self.path, &["--check"], CHECK_TIMEOUT) {
+            Ok(output) => parse_updates(&output).unwrap_or_else(|e| {
+                eprintln!("[{}]: Invalid --check output: {}", self.name(), e);
+                Vec::new()
+            }),
+            Err(error) => {
+                eprintln!("[{}]: --check failed: {}", self.name(), error);
+                Vec::new()
+            }
+        }
+    }
+
+    fn changelog(&self, update: &PendingUpdate) -> Option<String> {
+        if !self.capabilities().changelog {
+            return None;
+        }
+
+        let output = query(
+            &self.path,
+            &["--changelog", "--", &update.name],
+            CHECK_TIMEOUT,
+        )
+        .ok()?;
+        let changelog = output.trim().to_string();
+        (!changelog.is_empty()).then_some(changelog)
+    }
+
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        let mut args = vec!["--update".to_string()];
+        if let Selection::Only(names) = selection {
+            // Nothing to do if every update was deselected
+            if names.is_empty() {
+                let _ = tx.send(PluginEvent::status("No updates selected"));
+                return Ok(());
+            }
+            args.push("--".to_string());
+            args.extend(names.iter().cloned());
+        }
+
+        self.run_streaming(&args, &tx, settings, cancel)
+    }
+
+    fn rollback_target(&self) -> Option<RollbackTarget> {
+        let output = query(&self.path, &["--rollback-target"], QUERY_TIMEOUT).ok()?;
+        serde_json::from_str(&output).ok()
+    }
+
+    extern "Rust" fn rollback(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        settings: &PluginSettings,
+    ) -> Result<(), PluginError> {
+        self.run_streaming(
+            &["--rollback".to_string()],
+            &tx,
+            settings,
+            &CancellationToken::new(),
+        )
+    }
+}
+
+/// The pending updates a plugin printed for `--check`
+fn parse_updates(output: &str) -> serde_json::Result<Vec<PendingUpdate>> {
+    serde_json::from_str(output)
+}
+
+/// The event a plugin printed on a line of `stream`. stderr, and stdout that isn't an event,
+/// is passed through as output.
+fn event(stream: Stream, line: &str) -> PluginEvent {
+    let event = match stream {
+        Stream::Stdout => serde_json::from_str::<PluginEvent>(line).ok(),
+        Stream::Stderr => None,
+    };
+    event.unwrap_or_else(|| PluginEvent::log(stream, line))
+}
+
+/// Run the plugin at `path` with `args`, returning its stdout if it succeeds.
+///
+/// The plugin, and anything it started, is terminated if it hasn't finished after `timeout`.
+fn query(path: &str, args: &[&str], timeout: Duration) -> Result<String, String> {
+    let mut cmd = Command::new(path);
+    cmd.args(args);
+
+    // Cancelling terminates the plugin's process group
+    let cancel = CancellationToken::new();
+    let (done, finished) = flume::bounded::<()>(1);
+    let timer = cancel.clone();
+    thread::spawn(move || {
+        if let Err(flume::RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+            timer.cancel();
+        }
+    });
+
+    let (stdout, stderr, rc) = stream_command(cmd, &cancel, |_, _| {});
+    drop(done);
+
+    if cancel.is_cancelled() {
+        Err(format!("timed out after {} seconds", timeout.as_secs()))
+    } else if rc != 0 {
+        Err(stderr.trim().to_string())
+    } else {
+        Ok(stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    #[test]
+    fn parses_a_full_description() {
+        let plugin = ExternalPlugin::parse(
+            "/usr/lib/renovatio/plugins/my-tool",
+            r#"{
+                "name": "my-tool",
+                "description": "Update my-tool.",
+                "version": "1.2.0",
+                "conflicts": ["uupd"],
+                "after": ["brew"],
+                "resources": ["my-tool"],
+                "icon_name": "utilities-terminal-symbolic",
+                "privileges": "administrator",
+                "capabilities": {"check": true, "selective_update": false, "background_check": true},
+                "settings": [{"key": "fast", "title": "Fast Mode", "type": "bool", "default": false}]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(plugin.name(), "my-tool");
+        assert_eq!(plugin.version(), "1.2.0");
+        assert!(plugin.conflicts("uupd") && !plugin.conflicts("brew"));
+        assert_eq!(plugin.run_order().after, ["brew"]);
+        assert_eq!(plugin.resources(), ["my-tool"]);
+        assert_eq!(plugin.icon_name(), "utilities-terminal-symbolic");
+        assert_eq!(plugin.privileges(), Privileges::Administrator);
+        assert!(plugin.is_available().is_available());
+
+        let capabilities = plugin.capabilities();
+        assert!(capabilities.check && capabilities.background_check);
+        assert!(!capabilities.selective_update && !capabilities.rollback);
+        assert_eq!(plugin.settings()[0].key, "fast");
+    }
+
+    #[test]
+    fn fills_in_a_minimal_description() {
+        let plugin = ExternalPlugin::parse(
+            "/usr/lib/renovatio/plugins/my-tool",
+            r#"{"name": "my-tool", "unavailable": "my-tool isn't installed"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(plugin.privileges(), Privileges::User);
+        assert_eq!(plugin.resources(), ["*"]);
+        assert!(!plugin.conflicts("anything"));
+        assert!(plugin.capabilities().check && plugin.capabilities().selective_update);
+        assert!(!plugin.capabilities().changelog);
+        assert!(matches!(
+            plugin.is_available(),
+            Availability::Unavailable(reason) if reason == "my-tool isn't installed"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_malformed_description() {
+        for output in ["", "my-tool 1.0", r#"{"description": "No name"}"#, "[]"] {
+            let error = ExternalPlugin::parse("my-tool", output).err();
+            assert!(
+                error.is_some_and(|error| error.starts_with("Invalid --describe output")),
+                "{:?}",
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn parses_pending_updates() {
+        let updates = parse_updates(
+            r#"[
+                {"name": "foo", "current_version": "1.0", "new_version": "1.1"},
+                {"name": "bar", "kind": "package", "size": "2 MB", "unknown": true}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].name, "foo");
+        assert_eq!(updates[0].current_version.as_deref(), Some("1.0"));
+        assert_eq!(updates[0].new_version.as_deref(), Some("1.1"));
+        assert_eq!(updates[1].kind.as_deref(), Some("package"));
+        assert_eq!(updates[1].size.as_deref(), Some("2 MB"));
+
+        assert!(parse_updates("").is_err());
+        assert!(parse_updates(r#"{"name": "foo"}"#).is_err());
+    }
+
+    #[test]
+    fn parses_events_and_passes_other_lines_through() {
+        assert!(matches!(
+            event(Stream::Stdout, r#"{"event": "status", "message": "Upgrading"}"#),
+            PluginEvent::Status { message } if message == "Upgrading"
+        ));
+        assert!(matches!(
+            event(
+                Stream::Stdout,
+                r#"{"event": "step_finished", "item": "foo", "outcome": "succeeded"}"#
+            ),
+            PluginEvent::StepFinished { item, outcome: Outcome::Succeeded } if item == "foo"
+        ));
+        assert!(matches!(
+            event(Stream::Stdout, r#"{"event": "progress", "fraction": 0.5}"#),
+            PluginEvent::Progress { fraction: Some(fraction) } if fraction == 0.5
+        ));
+
+        // Plain output, unknown events, events missing a field, and events on stderr are output
+        for (stream, line) in [
+            (Stream::Stdout, "Downloading foo..."),
+            (Stream::Stdout, r#"{"event": "celebrate"}"#),
+            (Stream::Stdout, r#"{"event": "status"}"#),
+            (Stream::Stdout, r#"{"event": "status", "message": "#),
+            (
+                Stream::Stderr,
+                r#"{"event": "status", "message": "Upgrading"}"#,
+            ),
+        ] {
+            assert!(
+                matches!(
+                    event(stream, line),
+                    PluginEvent::Log { stream: logged, line: output }
+                        if logged == stream && output == line
+                ),
+                "{}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! The history of update runs.
+//!
+//! Each run, from the window, the command line or unattended, is appended to
+//! `$XDG_STATE_HOME/renovatio/history.jsonl` as a JSON object on its own line, so what
+//! happened can be looked up later, i.e. when a flatpak was updated and what broke. Only the
+//! most recent runs are kept, with the end of each plugin's output. Rollbacks are recorded
+//! the same way.
+
+use crate::config;
+
+use gio::prelude::*;
+use renovatio::engine::{EngineEvent, EngineState, PluginResult};
+use renovatio::{PluginEvent, Stream};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// The number of runs to keep
+const MAX_RUNS: usize = 200;
+
+/// The number of lines of each plugin's output to keep with a run, from the end
+const MAX_LOG_LINES: usize = 500;
+
+/// The number of characters to keep of each line of output, i.e. of a progress bar that
+/// redraws itself on one line
+const MAX_LINE_LENGTH: usize = 1000;
+
+/// Run records one run of the update engine
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Run {
+    /// When the run started, in seconds since the Unix epoch
+    pub started: i64,
+
+    /// Whether the run was started by the schedule, rather than by the user
+    #[serde(default)]
+    pub unattended: bool,
+
+    /// How each plugin's update went
+    pub results: Vec<PluginResult>,
+
+    /// The output of the plugins, in the order they printed it
+    #[serde(default)]
+    pub logs: Vec<LogLine>,
+}
+
+/// LogLine is a line of a plugin's output
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogLine {
+    pub plugin: String,
+    pub stream: Stream,
+    pub line: String,
+}
+
+impl Run {
+    /// Record a run that started now
+    pub fn new(unattended: bool) -> Self {
+        Self {
+            started: now(),
+            unattended,
+            results: Vec::new(),
+            logs: Vec::new(),
+        }
+    }
+
+    /// Record what the engine reported about the run
+    pub fn handle(&mut self, event: &EngineEvent) {
+        match event {
+            EngineEvent::Plugin {
+                plugin,
+                event: PluginEvent::Log { stream, line },
+            } => self.logs.push(LogLine {
+                plugin: plugin.clone(),
+                stream: *stream,
+                line: line.clone(),
+            }),
+            EngineEvent::StateChanged(EngineState::Finished(results)) => {
+                self.results = results.clone()
+            }
+            _ => {}
+        }
+    }
+
+    /// The output of `plugin`
+    pub fn logs_of<'a>(&'a self, plugin: &'a str) -> impl Iterator<Item = &'a LogLine> {
+        self.logs.iter().filter(move |log| log.plugin == plugin)
+    }
+
+    /// Determine if the run involved a plugin or an item matching `search`, ignoring case
+    pub fn matches(&self, search: &str) -> bool {
+        let search = search.to_lowercase();
+        self.results.iter().any(|result| {
+            result.name.to_lowercase().contains(&search)
+                || result
+                    .items
+                    .iter()
+                    .any(|item| item.name.to_lowercase().contains(&search))
+        })
+    }
+
+    /// When the run started, in the local time zone
+    pub fn started(&self) -> Option<glib::DateTime> {
+        glib::DateTime::from_unix_local(self.started).ok()
+    }
+
+    /// Summarize the run, i.e. "12 updated, 1 failed"
+    pub fn summary(&self) -> String {
+        let updated: usize = self
+            .results
+            .iter()
+            .map(|result| result.upgraded().count())
+            .sum();
+        let failed = self
+            .results
+            .iter()
+            .filter(|result| !result.success && !result.cancelled)
+            .count();
+
+        match (updated, failed) {
+            (0, 0) => "Nothing to update".to_string(),
+            (updated, 0) => format!("{} updated", updated),
+            (updated, failed) => format!(
+                "{} updated, {} {} failed",
+                updated,
+                failed,
+                if failed == 1 { "plugin" } else { "plugins" }
+            ),
+        }
+    }
+
+    /// The run with only the end of each plugin's output, which is where the errors are
+    fn trimmed(&self) -> Run {
+        let mut lines: HashMap<&str, usize> = HashMap::new();
+        for log in &self.logs {
+            *lines.entry(&log.plugin).or_default() += 1;
+        }
+        let mut skip: HashMap<String, usize> = lines
+            .into_iter()
+            .map(|(plugin, count)| (plugin.to_string(), count.saturating_sub(MAX_LOG_LINES)))
+            .collect();
+
+        let mut run = self.clone();
+        run.logs.retain(|log| match skip.get_mut(&log.plugin) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        });
+        for log in &mut run.logs {
+            if let Some((end, _)) = log.line.char_indices().nth(MAX_LINE_LENGTH) {
+                log.line.truncate(end);
+            }
+        }
+        run
+    }
+}
+
+/// The current time, in seconds since the Unix epoch
+fn now() -> i64 {
+    glib::DateTime::now_utc()
+        .map(|now| now.to_unix())
+        .unwrap_or_default()
+}
+
+/// Record the run whose events are sent to the returned sender, passing them on to `events`.
+///
+/// The run is recorded before the engine's `Finished` state is passed on, so it's in the
+/// history by the time the frontend hears the run is over.
+pub fn recorder(
+    events: flume::Sender<EngineEvent>,
+    unattended: bool,
+) -> flume::Sender<EngineEvent> {
+    let (tx, rx) = flume::unbounded::<EngineEvent>();
+
+    thread::spawn(move || {
+        let mut run = Run::new(unattended);
+        for event in rx.iter() {
+            run.handle(&event);
+            if matches!(event, EngineEvent::StateChanged(EngineState::Finished(_)))
+                && let Err(error) = record(&run)
+            {
+                eprintln!("Failed to record the update in the history: {}", error);
+            }
+            let _ = events.send(event);
+        }
+    });
+
+    tx
+}
+
+/// The file the history is kept in
+pub fn path() -> PathBuf {
+    glib::user_state_dir()
+        .join("renovatio")
+        .join("history.jsonl")
+}
+
+/// Append `run` to the history, forgetting the oldest runs once there are too many.
+///
+/// The history is replaced as a whole, so it's never left half written, while holding a lock
+/// so runs that finish at the same time, i.e. on the command line and unattended, are both kept.
+pub fn record(run: &Run) -> std::io::Result<()> {
+    let path = path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let _lock = lock(&path)?;
+
+    let history = std::fs::read_to_string(&path).unwrap_or_default();
+    let line = serde_json::to_string(&run.trimmed())?;
+
+    let temp = path.with_extension("jsonl.tmp");
+    std::fs::write(&temp, appended(&history, &line))?;
+    std::fs::rename(temp, path)
+}
+
+/// The `history` with the run on `line` appended, keeping the last `MAX_RUNS` runs
+fn appended(history: &str, line: &str) -> String {
+    let mut runs: Vec<&str> = history.lines().collect();
+    runs.push(line);
+    runs[runs.len().saturating_sub(MAX_RUNS)..].join("\n") + "\n"
+}
+
+/// Wait for the other processes recording a run to finish, holding the lock until the
+/// returned file is dropped
+fn lock(path: &Path) -> std::io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// Load every recorded run, oldest first, skipping any that can't be read
+pub fn load() -> Vec<Run> {
+    let Ok(history) = std::fs::read_to_string(path()) else {
+        return Vec::new();
+    };
+
+    history
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// The latest unattended run the user hasn't looked at yet, if any
+pub fn unseen_unattended_run() -> Option<Run> {
+    let settings = gio::Settings::new(config::APP_ID);
+    let seen = settings.int64("unattended-seen");
+
+    load()
+        .into_iter()
+        .rev()
+        .find(|run| run.unattended)
+        .filter(|run| run.started > seen)
+}
+
+/// Remember that the user has looked at `run`
+pub fn mark_seen(run: &Run) {
+    let settings = gio::Settings::new(config::APP_ID);
+    let _ = settings.set_int64("unattended-seen", run.started);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use renovatio::ItemResult;
+
+    use std::time::Duration;
+
+    /// How `plugin`'s update went, with whether each of its `items` updated
+    fn result(
+        plugin: &str,
+        success: bool,
+        cancelled: bool,
+        items: &[(&str, bool)],
+    ) -> PluginResult {
+        PluginResult {
+            name: plugin.to_string(),
+            success,
+            cancelled,
+            reboot_required: false,
+            items: items
+                .iter()
+                .map(|(name, success)| ItemResult::new(name, *success))
+                .collect(),
+            failure: None,
+            error: None,
+            elapsed: Duration::from_secs(1),
+        }
+    }
+
+    fn run(results: Vec<PluginResult>, logs: Vec<LogLine>) -> Run {
+        Run {
+            started: 1_750_000_000,
+            unattended: false,
+            results,
+            logs,
+        }
+    }
+
+    fn log(plugin: &str, line: String) -> LogLine {
+        LogLine {
+            plugin: plugin.to_string(),
+            stream: Stream::Stdout,
+            line,
+        }
+    }
+
+    #[test]
+    fn keeps_the_end_of_each_plugins_output() {
+        // The plugins' output is interleaved, as when they update at the same time
+        let logs = (0..MAX_LOG_LINES + 10)
+            .flat_map(|index| {
+                let mut logs = vec![log("flatpak", format!("flatpak {}", index))];
+                if index < 3 {
+                    logs.push(log("brew", format!("brew {}", index)));
+                }
+                logs
+            })
+            .collect();
+
+        let trimmed = run(Vec::new(), logs).trimmed();
+        let flatpak: Vec<&str> = trimmed
+            .logs_of("flatpak")
+            .map(|log| log.line.as_str())
+            .collect();
+        let brew: Vec<&str> = trimmed
+            .logs_of("brew")
+            .map(|log| log.line.as_str())
+            .collect();
+
+        assert_eq!(flatpak.len(), MAX_LOG_LINES);
+        assert_eq!(flatpak.first(), Some(&"flatpak 10"));
+        assert_eq!(
+            flatpak.last().copied(),
+            Some(format!("flatpak {}", MAX_LOG_LINES + 9).as_str())
+        );
+        assert_eq!(brew, ["brew 0", "brew 1", "brew 2"]);
+    }
+
+    #[test]
+    fn truncates_long_lines_between_characters() {
+        let line = "é".repeat(MAX_LINE_LENGTH + 5);
+        let trimmed = run(Vec::new(), vec![log("brew", line.clone())]).trimmed();
+
+        assert_eq!(trimmed.logs[0].line.chars().count(), MAX_LINE_LENGTH);
+        assert!(line.starts_with(&trimmed.logs[0].line));
+    }
+
+    #[test]
+    fn keeps_the_latest_runs() {
+        let history: String = (0..MAX_RUNS).map(|index| format!("{}\n", index)).collect();
+
+        let appended = appended(&history, "new");
+        let runs: Vec<&str> = appended.lines().collect();
+        assert_eq!(runs.len(), MAX_RUNS);
+        assert_eq!(runs.first(), Some(&"1"));
+        assert_eq!(runs.last(), Some(&"new"));
+
+        assert_eq!(super::appended("", "first"), "first\n");
+    }
+
+    #[test]
+    fn summarizes_the_updates_and_failures() {
+        assert_eq!(run(Vec::new(), Vec::new()).summary(), "Nothing to update");
+
+        let results = vec![
+            result("flatpak", true, false, &[("user/org.gnome.Maps", true)]),
+            result("brew", true, false, &[("git", true), ("wget", true)]),
+        ];
+        assert_eq!(run(results, Vec::new()).summary(), "3 updated");
+
+        // Failures are counted by plugin, and a cancelled plugin didn't fail
+        let results = vec![
+            result("flatpak", false, false, &[("user/org.gnome.Maps", false)]),
+            result("brew", false, false, &[("git", true)]),
+            result("distrobox", false, true, &[]),
+        ];
+        assert_eq!(
+            run(results, Vec::new()).summary(),
+            "1 updated, 2 plugins failed"
+        );
+
+        let results = vec![result("brew", false, false, &[])];
+        assert_eq!(
+            run(results, Vec::new()).summary(),
+            "0 updated, 1 plugin failed"
+        );
+    }
+
+    #[test]
+    fn matches_plugins_and_items_ignoring_case() {
+        let run = run(
+            vec![result(
+                "flatpak",
+                true,
+                false,
+                &[("user/org.gnome.Maps", true)],
+            )],
+            Vec::new(),
+        );
+        assert!(run.matches("FLATPAK"));
+        assert!(run.matches("org.gnome.maps"));
+        assert!(!run.matches("brew"));
+    }
+}
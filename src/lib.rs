@@ -1,45 +1,496 @@
-use std::process::Command;
+pub mod engine;
+pub mod external;
+pub mod loader;
+pub mod order;
+pub mod settings;
 
-#[derive(Clone, Debug)]
-/// PluginProgress is used to communicate the progress of a plugin update
-pub struct PluginProgress {
-    /// The name of the plugin this status belongs to
+use serde::{Deserialize, Serialize};
+use settings::{PluginSettings, Setting};
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// PluginEvent is sent by a plugin while it updates, to report what it's doing.
+///
+/// The engine sends `Started` before calling `Plugin::update` and `Finished` once it returns,
+/// so plugins only send the events in between.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PluginEvent {
+    /// The plugin started updating
+    Started,
+
+    /// The plugin moved on to something new, i.e. "Downloading updates..."
+    Status { message: String },
+
+    /// The plugin started updating an item, as reported by `Plugin::check`
+    StepStarted { item: PendingUpdate },
+
+    /// The plugin finished updating the item with this name
+    StepFinished { item: String, outcome: Outcome },
+
+    /// A line of output from a command the plugin ran
+    Log { stream: Stream, line: String },
+
+    /// How much of the update is done, from 0.0 to 1.0. `None` if the plugin can't tell,
+    /// in which case the progress bar pulses to show it's still working.
+    Progress { fraction: Option<f64> },
+
+    /// The update won't take effect until the system reboots
+    RebootRequired { reason: String },
+
+    /// The plugin finished updating
+    Finished { outcome: Outcome },
+}
+
+impl PluginEvent {
+    /// A new status, i.e. "Downloading updates..."
+    pub fn status(message: &str) -> Self {
+        PluginEvent::Status {
+            message: message.to_string(),
+        }
+    }
+
+    /// A line of output from a command
+    pub fn log(stream: Stream, line: &str) -> Self {
+        PluginEvent::Log {
+            stream,
+            line: line.to_string(),
+        }
+    }
+
+    /// How much of the update is done, from 0.0 to 1.0
+    pub fn progress(fraction: f64) -> Self {
+        PluginEvent::Progress {
+            fraction: Some(fraction.clamp(0.0, 1.0)),
+        }
+    }
+
+    /// The plugin is working, but can't tell how far along it is
+    pub fn pulse() -> Self {
+        PluginEvent::Progress { fraction: None }
+    }
+}
+
+/// Outcome describes how updating a plugin, or one of its items, ended
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Succeeded,
+
+    /// The update failed, with the error output if there is any
+    Failed {
+        error: Option<String>,
+    },
+
+    /// The update was stopped before it finished
+    Cancelled,
+}
+
+impl Outcome {
+    /// Succeeded or failed, depending on `success`
+    pub fn from_success(success: bool) -> Self {
+        if success {
+            Outcome::Succeeded
+        } else {
+            Outcome::Failed { error: None }
+        }
+    }
+
+    /// Determine if the update succeeded
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Succeeded)
+    }
+}
+
+/// PluginError describes why a plugin's update failed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PluginError {
+    /// A command the plugin needs isn't installed
+    CommandNotFound(String),
+
+    /// The plugin wasn't allowed to make changes, i.e. authentication was dismissed
+    PermissionDenied(String),
+
+    /// The plugin couldn't reach the network
+    NetworkUnavailable(String),
+
+    /// The plugin couldn't understand the output of a command
+    Parse(String),
+
+    /// Some of the items failed to update, named here. The others may have succeeded.
+    PartialFailure { failed: Vec<String> },
+
+    /// The update failed for another reason
+    Failed(String),
+
+    /// The plugin panicked, set by the engine
+    Panicked(String),
+}
+
+impl PluginError {
+    /// Work out why `command` failed from its exit code and error output
+    pub fn from_command(command: &str, stderr: &str, rc: i32) -> Self {
+        let output = stderr.trim();
+        let lower = output.to_lowercase();
+        let detail = output
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("")
+            .to_string();
+
+        // The program being run, rather than pkexec running it
+        let program = command
+            .split_whitespace()
+            .find(|word| *word != "pkexec")
+            .unwrap_or(command)
+            .to_string();
+        let elevated = command.starts_with("pkexec");
+
+        if !elevated && rc == 127 {
+            PluginError::CommandNotFound(program)
+        } else if (elevated && matches!(rc, 126 | 127))
+            || lower.contains("permission denied")
+            || lower.contains("not authorized")
+        {
+            PluginError::PermissionDenied(detail)
+        } else if [
+            "could not resolve",
+            "temporary failure in name resolution",
+            "network is unreachable",
+            "no route to host",
+            "connection timed out",
+        ]
+        .iter()
+        .any(|message| lower.contains(message))
+        {
+            PluginError::NetworkUnavailable(detail)
+        } else if detail.is_empty() {
+            PluginError::Failed(format!("{} exited with {}", program, rc))
+        } else {
+            PluginError::Failed(detail)
+        }
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PluginError::CommandNotFound(command) => write!(f, "{} isn't installed", command),
+            PluginError::PermissionDenied(detail) if detail.is_empty() => {
+                write!(f, "Permission denied")
+            }
+            PluginError::PermissionDenied(detail) => write!(f, "Permission denied: {}", detail),
+            PluginError::NetworkUnavailable(detail) => {
+                write!(f, "The network is unavailable: {}", detail)
+            }
+            PluginError::Parse(detail) => write!(f, "Couldn't understand the output: {}", detail),
+            PluginError::PartialFailure { failed } => {
+                write!(f, "{} failed to update", failed.join(", "))
+            }
+            PluginError::Failed(detail) => write!(f, "{}", detail),
+            PluginError::Panicked(detail) => write!(f, "The plugin crashed: {}", detail),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// ItemResult describes the outcome of updating a single package, application or image
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ItemResult {
+    /// The name of the item, as reported by `Plugin::check`
     pub name: String,
 
-    /// The progress of the update, from 0 to 100
-    pub progress: u32,
+    /// Whether the item was updated successfully
+    pub success: bool,
 
-    /// In lieu of incremental progress, this will pulse the ProgressBar
-    /// to indicate that progress is being made.
-    pub pulse: bool,
+    /// The version before the update, if known
+    pub old_version: Option<String>,
 
-    // Indicate if the update requires a reboot
-    pub reboot_required: bool,
+    /// The version after the update, if known
+    pub new_version: Option<String>,
 
-    /// The status of the update
-    pub status: String,
+    /// The error output, if the update failed
+    pub error: Option<String>,
+}
 
-    /// The standard output from the update, if available
-    pub stdout: Option<String>,
+impl ItemResult {
+    pub fn new(name: &str, success: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            success,
+            ..Default::default()
+        }
+    }
 
-    /// The standard error from the update, if available
-    pub stderr: Option<String>,
+    /// Create the result of applying a pending update
+    pub fn from_update(update: &PendingUpdate, outcome: &Outcome) -> Self {
+        Self {
+            name: update.name.clone(),
+            success: outcome.is_success(),
+            old_version: update.current_version.clone(),
+            new_version: update.new_version.clone(),
+            error: match outcome {
+                Outcome::Failed { error } => error.clone(),
+                _ => None,
+            },
+        }
+    }
 }
 
-impl PluginProgress {
+/// The kind of the pending update of the OS itself, whichever plugin updates it
+pub const OS_KIND: &str = "system";
+
+/// PendingUpdate describes a single update a plugin could apply
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PendingUpdate {
+    /// The name of the package, application, image or container to be updated
+    pub name: String,
+
+    /// What kind of item this is, i.e. "formula", "cask", "user flatpak" or `OS_KIND`
+    pub kind: Option<String>,
+
+    /// The currently installed version, if known
+    pub current_version: Option<String>,
+
+    /// The version that will be installed, if known
+    pub new_version: Option<String>,
+
+    /// The download size of the update, if known
+    pub size: Option<String>,
+}
+
+impl PendingUpdate {
     pub fn new(name: &str) -> Self {
         Self {
             name: name.to_string(),
-            progress: 0,
-            pulse: false,
-            reboot_required: false,
-            status: String::new(),
-            stdout: None,
-            stderr: None,
+            ..Default::default()
+        }
+    }
+}
+
+/// Deployment describes an installed version of the OS, i.e. the one that's booted
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Deployment {
+    /// The version of the image, if known
+    pub version: Option<String>,
+
+    /// The digest of the image, or the checksum of the commit it was deployed from
+    pub digest: Option<String>,
+
+    /// Whether the deployment is pinned, so updates don't remove it
+    pub pinned: bool,
+}
+
+/// RollbackTarget describes what rolling back would go back to
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RollbackTarget {
+    /// What's running now
+    pub booted: Deployment,
+
+    /// What rolling back goes back to
+    pub rollback: Deployment,
+
+    /// Whether the rollback will already be booted next time
+    pub queued: bool,
+}
+
+/// Selection describes which pending updates a plugin should apply
+#[derive(Clone, Debug, Default)]
+pub enum Selection {
+    /// Apply every update the plugin finds
+    #[default]
+    All,
+
+    /// Only apply the updates with these names, as reported by `Plugin::check`
+    Only(Vec<String>),
+}
+
+impl Selection {
+    /// Determine if the update with the given name was selected
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            Selection::All => true,
+            Selection::Only(names) => names.iter().any(|n| n == name),
         }
     }
 }
 
+/// RunOrder declares when a plugin should run, relative to the other enabled plugins.
+///
+/// `"*"` stands for every other plugin, i.e. `after: ["*"]` runs a plugin last.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunOrder {
+    /// The plugins this plugin should run before
+    pub before: Vec<String>,
+
+    /// The plugins this plugin should run after
+    pub after: Vec<String>,
+}
+
+impl RunOrder {
+    /// Run before the named plugins
+    pub fn before(names: &[&str]) -> Self {
+        Self {
+            before: names.iter().map(|name| name.to_string()).collect(),
+            after: Vec::new(),
+        }
+    }
+
+    /// Run after the named plugins
+    pub fn after(names: &[&str]) -> Self {
+        Self {
+            before: Vec::new(),
+            after: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+/// Privileges describes what a plugin has to be allowed to do to update
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Privileges {
+    /// The plugin only changes things the user owns
+    #[default]
+    User,
+
+    /// The plugin changes the system, asking for authentication to do so
+    Administrator,
+}
+
+/// Capabilities describes what a plugin can do besides applying every update
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    /// The plugin can list its pending updates without applying them
+    pub check: bool,
+
+    /// The plugin can apply some of its pending updates, leaving the others
+    pub selective_update: bool,
+
+    /// The plugin can go back to what was installed before the last update
+    pub rollback: bool,
+
+    /// The plugin can describe what's new in its pending updates
+    pub changelog: bool,
+
+    /// Checking never asks for authentication, so it can happen in the background. Only
+    /// matters for plugins that need an administrator's privileges to update.
+    pub background_check: bool,
+}
+
+/// Availability describes whether a plugin can run on this system
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Availability {
+    #[default]
+    Available,
+
+    /// The plugin can't run here, i.e. because the tool it drives isn't installed
+    Unavailable(String),
+}
+
+impl Availability {
+    /// Available if `program` is installed, otherwise unavailable saying it isn't
+    pub fn requires(program: &str) -> Self {
+        if which(program).is_some() {
+            Availability::Available
+        } else {
+            Availability::Unavailable(format!("{} isn't installed", program))
+        }
+    }
+
+    /// Determine if the plugin can run
+    pub fn is_available(&self) -> bool {
+        matches!(self, Availability::Available)
+    }
+
+    /// Why the plugin can't run, if it can't
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Availability::Available => None,
+            Availability::Unavailable(reason) => Some(reason),
+        }
+    }
+}
+
+/// CancellationToken is shared between a frontend and a running update, to stop it early.
+///
+/// Plugins should check `is_cancelled` between items. Commands run with `execute_streaming`
+/// or `stream_command` get their own process group, which is terminated on `cancel`.
+/// Commands running as another user, i.e. via pkexec, can't be signalled and are left to finish.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<CancellationState>,
+}
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+
+    /// The process groups of the commands currently running
+    process_groups: Mutex<Vec<i32>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the update, terminating any running commands
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+
+        if let Ok(process_groups) = self.inner.process_groups.lock() {
+            for process_group in process_groups.iter() {
+                terminate_process_group(*process_group);
+            }
+        }
+    }
+
+    /// Determine if the update was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Keep track of a running command, so it's terminated if the update is cancelled
+    fn register(&self, process_group: i32) {
+        if let Ok(mut process_groups) = self.inner.process_groups.lock() {
+            process_groups.push(process_group);
+        }
+
+        // The update may have been cancelled while the command was starting
+        if self.is_cancelled() {
+            terminate_process_group(process_group);
+        }
+    }
+
+    fn unregister(&self, process_group: i32) {
+        if let Ok(mut process_groups) = self.inner.process_groups.lock() {
+            process_groups.retain(|pg| *pg != process_group);
+        }
+    }
+}
+
+fn terminate_process_group(process_group: i32) {
+    // A negative pid signals every process in the group
+    unsafe {
+        libc::kill(-process_group, libc::SIGTERM);
+    }
+}
+
 /// PluginMetadata is used to describe a plugin
 #[derive(Clone, Debug)]
 pub struct PluginMetadata {
@@ -47,67 +498,338 @@ pub struct PluginMetadata {
     pub version: String,
     pub description: String,
     pub path: Option<String>,
+
+    /// The names of the other installed plugins this plugin conflicts with
+    pub conflicts: Vec<String>,
+
+    /// When the plugin should run, relative to the others
+    pub run_order: RunOrder,
+
+    /// Whether the plugin can run on this system
+    pub availability: Availability,
+
+    /// The name of the icon representing the plugin
+    pub icon_name: String,
+
+    pub author: String,
+    pub homepage: String,
+
+    /// What the plugin has to be allowed to do to update
+    pub privileges: Privileges,
+
+    /// What the plugin can do besides applying every update
+    pub capabilities: Capabilities,
+
+    /// The settings the plugin understands
+    pub settings: Vec<Setting>,
 }
 
 impl PluginMetadata {
     /// Create a new plugin metadata
-    pub fn new(plugin: Box<dyn Plugin>) -> Self {
+    pub fn new(plugin: &dyn Plugin) -> Self {
         Self {
             name: plugin.name().to_string(),
             version: plugin.version().to_string(),
             description: plugin.description().to_string(),
             path: None,
+            conflicts: Vec::new(),
+            run_order: plugin.run_order(),
+            availability: plugin.is_available(),
+            icon_name: plugin.icon_name().to_string(),
+            author: plugin.author().to_string(),
+            homepage: plugin.homepage().to_string(),
+            privileges: plugin.privileges(),
+            capabilities: plugin.capabilities(),
+            settings: plugin.settings(),
         }
     }
+
+    /// Determine if the plugin can check for updates without asking for authentication
+    pub fn can_check_in_background(&self) -> bool {
+        self.capabilities.check
+            && (self.privileges == Privileges::User || self.capabilities.background_check)
+    }
 }
 
 /// Plugin is a trait that defines the interface for a plugin.
-pub trait Plugin {
+///
+/// Plugins may update at the same time as other plugins, on their own threads.
+pub trait Plugin: Send + Sync {
     /// The name of the plugin
     fn name(&self) -> &str;
 
     /// The description of the plugin
     fn description(&self) -> &str;
 
-    /// The version of the plugin
+    /// The version of the plugin, usually `env!("CARGO_PKG_VERSION")`
     fn version(&self) -> &str;
 
+    /// The name of the icon representing the plugin
+    fn icon_name(&self) -> &str {
+        "application-x-addon-symbolic"
+    }
+
+    /// Who wrote the plugin
+    fn author(&self) -> &str {
+        ""
+    }
+
+    /// Where to find out more about the plugin
+    fn homepage(&self) -> &str {
+        ""
+    }
+
+    /// What the plugin has to be allowed to do to update
+    fn privileges(&self) -> Privileges {
+        Privileges::User
+    }
+
+    /// What the plugin can do besides applying every update
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// The settings this plugin understands, shown on its page in the preferences. Their
+    /// values are passed to `update`.
+    fn settings(&self) -> Vec<Setting> {
+        Vec::new()
+    }
+
     /// Determine if this plugin conflicts with another plugin.
     fn conflicts(&self, plugin_name: &str) -> bool;
 
+    /// When this plugin should run, relative to the other enabled plugins
+    fn run_order(&self) -> RunOrder {
+        RunOrder::default()
+    }
+
+    /// The system state this plugin changes, i.e. "os" or "flatpak". Plugins that share a
+    /// resource never update at the same time. `"*"`, the default, updates the plugin on its own.
+    fn resources(&self) -> Vec<String> {
+        vec!["*".to_string()]
+    }
+
+    /// Whether this plugin can run on this system, i.e. if the tool it drives is installed.
+    /// Unavailable plugins can't be enabled, and are left out of updates.
+    fn is_available(&self) -> Availability {
+        Availability::Available
+    }
+
+    /// Check for pending updates without applying them
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PendingUpdate>` - The updates that `update` would apply
+    fn check(&self) -> Vec<PendingUpdate>;
+
+    /// Describe what's new in one of the updates `check` returned, i.e. its release notes or
+    /// the packages it changes. Renovatio caches the description, so this can be slow.
+    fn changelog(&self, _update: &PendingUpdate) -> Option<String> {
+        None
+    }
+
     /// Run a blocking update
     ///
     /// # Arguments
     ///
-    /// * `tx` - The sender channel to send progress updates to
+    /// * `tx` - The sender channel to report the update's `PluginEvent`s to
+    /// * `selection` - The pending updates to apply
+    /// * `settings` - The value of each of the plugin's `settings`
+    /// * `cancel` - Stop as soon as possible once this is cancelled
     ///
     /// # Returns
     ///
-    /// * `bool` - Whether the update was successful
-    extern "Rust" fn update(&self, tx: flume::Sender<PluginProgress>) -> bool;
+    /// * `Result<(), PluginError>` - Why the update failed, if it did
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError>;
+
+    /// What `rollback` would go back to, or `None` if there's nothing to go back to
+    fn rollback_target(&self) -> Option<RollbackTarget> {
+        None
+    }
+
+    /// Go back to what was installed before the last update, reporting `PluginEvent`s like
+    /// `update` does. Only called if the plugin has the `rollback` capability.
+    extern "Rust" fn rollback(
+        &self,
+        _tx: flume::Sender<PluginEvent>,
+        _settings: &PluginSettings,
+    ) -> Result<(), PluginError> {
+        Err(PluginError::Failed(format!(
+            "{} can't roll back",
+            self.name()
+        )))
+    }
+}
+
+/// Stream identifies the output stream a line of output came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Find `program` on the `PATH`, like `which`
+pub fn which(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| {
+            candidate.metadata().is_ok_and(|metadata| {
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            })
+        })
+}
+
+/// Determine if the system was booted from an ostree deployment, i.e. by bootc or rpm-ostree
+pub fn is_ostree_booted() -> bool {
+    Path::new("/run/ostree-booted").exists()
+}
+
+/// Determine if we're running inside a container, i.e. a toolbox or distrobox
+pub fn in_container() -> bool {
+    Path::new("/run/.containerenv").exists() || Path::new("/.dockerenv").exists()
 }
 
 /// Execute a command and return it's stdout, stderr, and success/failure
 pub fn execute(command: &str) -> (String, String, i32) {
+    execute_streaming(command, &CancellationToken::new(), |_, _| {})
+}
+
+/// Execute a command, calling `on_line` with each line of stdout and stderr as it arrives.
+///
+/// The command is terminated if `cancel` is cancelled.
+///
+/// Returns the complete stdout and stderr, regardless of the exit code, and the exit code.
+pub fn execute_streaming(
+    command: &str,
+    cancel: &CancellationToken,
+    on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    stream_command(cmd, cancel, on_line)
+}
+
+/// Run `cmd`, calling `on_line` with each line of stdout and stderr as it arrives.
+///
+/// The command runs in its own process group, which is terminated if `cancel` is cancelled.
+///
+/// Returns the complete stdout and stderr, regardless of the exit code, and the exit code.
+pub fn stream_command(
+    mut cmd: Command,
+    cancel: &CancellationToken,
+    mut on_line: impl FnMut(Stream, &str),
+) -> (String, String, i32) {
     let mut stdout = String::new();
     let mut stderr = String::new();
-    let mut rc = 1; // default to non-zero
 
-    let cmd = Command::new("sh").args(["-c", command]).output();
+    if cancel.is_cancelled() {
+        return (stdout, stderr, 1);
+    }
 
-    match cmd {
-        Ok(output) => {
-            rc = output.status.code().unwrap_or(0);
-            if output.status.success() {
-                stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            } else {
-                stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            }
-        }
+    let mut child = match cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+    {
+        Ok(child) => child,
         Err(error) => {
             eprintln!("Error executing command: {}", error);
+            let error = error.to_string();
+            on_line(Stream::Stderr, &error);
+            return (stdout, error, 1);
         }
+    };
+
+    // The child leads its own process group, so its pid is the group's id
+    let process_group = child.id() as i32;
+    cancel.register(process_group);
+
+    // Read both streams on their own threads, so neither can block on a full pipe
+    let (tx, rx) = flume::unbounded::<(Stream, String)>();
+    let mut readers = Vec::new();
+    if let Some(pipe) = child.stdout.take() {
+        readers.push(read_lines(pipe, Stream::Stdout, tx.clone()));
     }
+    if let Some(pipe) = child.stderr.take() {
+        readers.push(read_lines(pipe, Stream::Stderr, tx.clone()));
+    }
+    drop(tx);
+
+    for (stream, line) in rx.iter() {
+        on_line(stream, &line);
+
+        let output = match stream {
+            Stream::Stdout => &mut stdout,
+            Stream::Stderr => &mut stderr,
+        };
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    // A process killed by a signal has no exit code, so treat it as a failure
+    let rc = child
+        .wait()
+        .map(|status| status.code().unwrap_or(1))
+        .unwrap_or(1);
+    cancel.unregister(process_group);
 
     (stdout, stderr, rc)
 }
+
+fn read_lines(
+    pipe: impl Read + Send + 'static,
+    stream: Stream,
+    tx: flume::Sender<(Stream, String)>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        // Output isn't always UTF-8, and the pipe has to be read to the end either way, so the
+        // command doesn't get SIGPIPE
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while reader
+            .read_until(b'\n', &mut line)
+            .is_ok_and(|read| read > 0)
+        {
+            if line.ends_with(b"\n") {
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+            }
+            let _ = tx.send((stream, String::from_utf8_lossy(&line).into_owned()));
+            line.clear();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_output_that_isnt_utf8() {
+        let mut lines = Vec::new();
+        let (stdout, _stderr, rc) = execute_streaming(
+            r"printf 'before\n\377\376\nafter\r\nlast'",
+            &CancellationToken::new(),
+            |_, line| lines.push(line.to_string()),
+        );
+
+        assert_eq!(rc, 0);
+        assert_eq!(lines, ["before", "\u{fffd}\u{fffd}", "after", "last"]);
+        assert_eq!(stdout, "before\n\u{fffd}\u{fffd}\nafter\nlast\n");
+    }
+}
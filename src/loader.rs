@@ -0,0 +1,383 @@
+//! Loading plugins from shared libraries and executables.
+//!
+//! Executables are loaded as `ExternalPlugin`s, see the `external` module.
+//!
+//! A plugin is only ABI compatible with renovatio if it was built by the same compiler,
+//! against the same version of this crate and the same versions of the crates whose types
+//! cross the boundary, i.e. flume. Plugins export `RENOVATIO_ABI_VERSION`, which is checked
+//! before anything else is read from the library, and a `#[repr(C)]` `PluginDeclaration`
+//! describing how they were built. The `Plugin` trait object is only created once all of
+//! those match. Use `export_plugin!` to export both.
+
+use crate::external::ExternalPlugin;
+use crate::settings::{PluginSettings, Setting};
+use crate::{
+    Availability, CancellationToken, Capabilities, PendingUpdate, Plugin, PluginError, PluginEvent,
+    Privileges, RollbackTarget, RunOrder, Selection,
+};
+
+use libloading::{Library, Symbol};
+
+use std::any::Any;
+use std::ffi::{CStr, c_char, c_void};
+use std::fmt;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+
+/// The version of the plugin ABI. Bump this whenever `PluginDeclaration` or the `Plugin`
+/// trait changes.
+pub const ABI_VERSION: u32 = 13;
+
+/// The version of renovatio the plugin was built against
+pub const RENOVATIO_VERSION: &CStr = c_str(concat!(env!("CARGO_PKG_VERSION"), "\0"));
+
+/// The version of rustc the plugin was built with
+pub const RUSTC_VERSION: &CStr = c_str(concat!(env!("RENOVATIO_RUSTC_VERSION"), "\0"));
+
+/// The locked versions of the crates whose types cross the boundary, i.e. "flume 0.11.1"
+pub const DEPENDENCIES: &CStr = c_str(concat!(env!("RENOVATIO_DEPENDENCIES"), "\0"));
+
+const fn c_str(s: &str) -> &CStr {
+    match CStr::from_bytes_with_nul(s.as_bytes()) {
+        Ok(s) => s,
+        Err(_) => panic!("Invalid C string"),
+    }
+}
+
+/// PluginDeclaration is exported by every plugin to describe how it was built
+#[repr(C)]
+pub struct PluginDeclaration {
+    /// The rustc version the plugin was built with
+    pub rustc_version: *const c_char,
+
+    /// The renovatio version the plugin was built against
+    pub renovatio_version: *const c_char,
+
+    /// The versions of the shared crates the plugin was built against
+    pub dependencies: *const c_char,
+
+    /// Create an instance of the plugin, as a `*mut Box<dyn Plugin>`
+    pub create: unsafe extern "C" fn() -> *mut c_void,
+}
+
+// The declaration only points to static strings and a function
+unsafe impl Sync for PluginDeclaration {}
+
+/// Export a plugin so that renovatio can load it
+///
+/// # Example
+///
+/// ```ignore
+/// renovatio::export_plugin!(Brew);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:expr) => {
+        #[unsafe(no_mangle)]
+        pub static RENOVATIO_ABI_VERSION: u32 = $crate::loader::ABI_VERSION;
+
+        #[unsafe(no_mangle)]
+        pub static RENOVATIO_PLUGIN_DECLARATION: $crate::loader::PluginDeclaration =
+            $crate::loader::PluginDeclaration {
+                rustc_version: $crate::loader::RUSTC_VERSION.as_ptr(),
+                renovatio_version: $crate::loader::RENOVATIO_VERSION.as_ptr(),
+                dependencies: $crate::loader::DEPENDENCIES.as_ptr(),
+                create: {
+                    unsafe extern "C" fn create() -> *mut ::std::ffi::c_void {
+                        let plugin: Box<dyn $crate::Plugin> =
+                            Box::new($crate::loader::PanicGuard($plugin));
+                        Box::into_raw(Box::new(plugin)).cast()
+                    }
+                    create
+                },
+            };
+    };
+}
+
+/// PanicGuard catches a shared library plugin's panics, turning them into errors.
+///
+/// A plugin has its own copy of the standard library, so renovatio can't catch its panics:
+/// they have to be caught on the plugin's side of the boundary. `export_plugin!` wraps every
+/// plugin in one.
+#[doc(hidden)]
+pub struct PanicGuard<P: Plugin>(pub P);
+
+impl<P: Plugin> Plugin for PanicGuard<P> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+
+    fn version(&self) -> &str {
+        self.0.version()
+    }
+
+    fn icon_name(&self) -> &str {
+        self.0.icon_name()
+    }
+
+    fn author(&self) -> &str {
+        self.0.author()
+    }
+
+    fn homepage(&self) -> &str {
+        self.0.homepage()
+    }
+
+    fn privileges(&self) -> Privileges {
+        self.0.privileges()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    fn settings(&self) -> Vec<Setting> {
+        self.0.settings()
+    }
+
+    fn conflicts(&self, plugin_name: &str) -> bool {
+        self.0.conflicts(plugin_name)
+    }
+
+    fn run_order(&self) -> RunOrder {
+        self.0.run_order()
+    }
+
+    fn resources(&self) -> Vec<String> {
+        self.0.resources()
+    }
+
+    fn is_available(&self) -> Availability {
+        panic::catch_unwind(AssertUnwindSafe(|| self.0.is_available()))
+            .unwrap_or_else(|payload| Availability::Unavailable(panic_message(payload.as_ref())))
+    }
+
+    fn check(&self) -> Vec<PendingUpdate> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.0.check())).unwrap_or_else(|payload| {
+            eprintln!(
+                "[{}]: Check failed: {}",
+                self.0.name(),
+                panic_message(payload.as_ref())
+            );
+            Vec::new()
+        })
+    }
+
+    fn changelog(&self, update: &PendingUpdate) -> Option<String> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.0.changelog(update))).unwrap_or_else(
+            |payload| {
+                eprintln!(
+                    "[{}]: Changelog failed: {}",
+                    self.0.name(),
+                    panic_message(payload.as_ref())
+                );
+                None
+            },
+        )
+    }
+
+    extern "Rust" fn update(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        selection: &Selection,
+        settings: &PluginSettings,
+        cancel: &CancellationToken,
+    ) -> Result<(), PluginError> {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            self.0.update(tx, selection, settings, cancel)
+        }))
+        .unwrap_or_else(|payload| Err(PluginError::Panicked(panic_message(payload.as_ref()))))
+    }
+
+    fn rollback_target(&self) -> Option<RollbackTarget> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.0.rollback_target())).unwrap_or_else(
+            |payload| {
+                eprintln!(
+                    "[{}]: Finding the rollback failed: {}",
+                    self.0.name(),
+                    panic_message(payload.as_ref())
+                );
+                None
+            },
+        )
+    }
+
+    extern "Rust" fn rollback(
+        &self,
+        tx: flume::Sender<PluginEvent>,
+        settings: &PluginSettings,
+    ) -> Result<(), PluginError> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.0.rollback(tx, settings)))
+            .unwrap_or_else(|payload| Err(PluginError::Panicked(panic_message(payload.as_ref()))))
+    }
+}
+
+/// The message a plugin panicked with
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// LoadError describes why a plugin could not be loaded
+#[derive(Clone, Debug)]
+pub enum LoadError {
+    /// The shared library could not be opened
+    Library(String),
+
+    /// The shared library doesn't export a renovatio plugin
+    NotAPlugin,
+
+    /// The plugin was built against a different plugin ABI
+    AbiVersion { expected: u32, found: u32 },
+
+    /// The plugin was built with a different compiler
+    RustcVersion { expected: String, found: String },
+
+    /// The plugin was built against a different version of renovatio
+    RenovatioVersion { expected: String, found: String },
+
+    /// The plugin was built against different versions of the crates it shares with renovatio
+    Dependencies { expected: String, found: String },
+
+    /// The executable didn't describe itself
+    Describe(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Library(error) => write!(f, "Failed to open the plugin: {}", error),
+            LoadError::NotAPlugin => write!(f, "Not a renovatio plugin"),
+            LoadError::AbiVersion { expected, found } => write!(
+                f,
+                "Plugin ABI version {} is not supported (expected {})",
+                found, expected
+            ),
+            LoadError::RustcVersion { expected, found } => write!(
+                f,
+                "Plugin was built with {}, but renovatio was built with {}. Rebuild the plugin.",
+                found, expected
+            ),
+            LoadError::RenovatioVersion { expected, found } => write!(
+                f,
+                "Plugin was built for renovatio {}, but this is renovatio {}. Rebuild the plugin.",
+                found, expected
+            ),
+            LoadError::Dependencies { expected, found } => write!(
+                f,
+                "Plugin was built against {}, but renovatio was built against {}. Rebuild the plugin.",
+                found, expected
+            ),
+            LoadError::Describe(error) => write!(f, "Not a renovatio plugin: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// A plugin instantiated from a shared library or an executable.
+///
+/// A shared library plugin's code lives in the library, so the library is kept loaded until
+/// the plugin has been dropped (fields are dropped in declaration order).
+pub struct LoadedPlugin {
+    plugin: Box<dyn Plugin>,
+    _library: Option<Library>,
+}
+
+impl LoadedPlugin {
+    /// Wrap a plugin that's compiled into the application, i.e. for testing
+    pub fn new(plugin: Box<dyn Plugin>) -> Self {
+        Self {
+            plugin,
+            _library: None,
+        }
+    }
+}
+
+impl Deref for LoadedPlugin {
+    type Target = dyn Plugin;
+
+    fn deref(&self) -> &Self::Target {
+        self.plugin.as_ref()
+    }
+}
+
+/// Load the plugin at `path`, which is either a shared library or an executable
+pub fn load_plugin(path: &str) -> Result<LoadedPlugin, LoadError> {
+    if path.ends_with(".so") {
+        load_library(path)
+    } else {
+        let plugin = ExternalPlugin::describe(path).map_err(LoadError::Describe)?;
+        Ok(LoadedPlugin::new(Box::new(plugin)))
+    }
+}
+
+/// Load the shared library at `path` and instantiate its plugin
+fn load_library(path: &str) -> Result<LoadedPlugin, LoadError> {
+    unsafe {
+        let library = Library::new(path).map_err(|e| LoadError::Library(e.to_string()))?;
+
+        let plugin = {
+            // Check the ABI version before trusting the layout of anything else in the library
+            let abi_version: Symbol<*const u32> = library
+                .get(b"RENOVATIO_ABI_VERSION\0")
+                .map_err(|_| LoadError::NotAPlugin)?;
+            let abi_version = **abi_version;
+            if abi_version != ABI_VERSION {
+                return Err(LoadError::AbiVersion {
+                    expected: ABI_VERSION,
+                    found: abi_version,
+                });
+            }
+
+            let declaration: Symbol<*const PluginDeclaration> = library
+                .get(b"RENOVATIO_PLUGIN_DECLARATION\0")
+                .map_err(|_| LoadError::NotAPlugin)?;
+            let declaration = &**declaration;
+
+            // The `Plugin` trait object is only compatible if it was built the same way
+            let rustc_version = CStr::from_ptr(declaration.rustc_version);
+            if rustc_version != RUSTC_VERSION {
+                return Err(LoadError::RustcVersion {
+                    expected: RUSTC_VERSION.to_string_lossy().to_string(),
+                    found: rustc_version.to_string_lossy().to_string(),
+                });
+            }
+
+            let renovatio_version = CStr::from_ptr(declaration.renovatio_version);
+            if renovatio_version != RENOVATIO_VERSION {
+                return Err(LoadError::RenovatioVersion {
+                    expected: RENOVATIO_VERSION.to_string_lossy().to_string(),
+                    found: renovatio_version.to_string_lossy().to_string(),
+                });
+            }
+
+            // The types crossing the boundary come from these crates, so they must match too
+            let dependencies = CStr::from_ptr(declaration.dependencies);
+            if dependencies != DEPENDENCIES {
+                return Err(LoadError::Dependencies {
+                    expected: DEPENDENCIES.to_string_lossy().to_string(),
+                    found: dependencies.to_string_lossy().to_string(),
+                });
+            }
+
+            // Reclaim ownership of the plugin
+            let plugin: Box<Box<dyn Plugin>> = Box::from_raw((declaration.create)().cast());
+            *plugin
+        };
+
+        Ok(LoadedPlugin {
+            plugin,
+            _library: Some(library),
+        })
+    }
+}
@@ -0,0 +1,217 @@
+use gtk::prelude::*;
+use gtk::{Box, Button, Expander, ScrolledWindow, SearchEntry, TextBuffer, TextTag, TextView};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// LogView shows the stdout and stderr of each plugin in its own expandable pane
+#[derive(Clone)]
+pub struct LogView {
+    pub container: Box,
+    logs: Rc<RefCell<HashMap<String, PluginLog>>>,
+}
+
+/// The log pane of a single plugin
+struct PluginLog {
+    expander: Expander,
+    view: TextView,
+    buffer: TextBuffer,
+}
+
+impl Default for LogView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogView {
+    pub fn new() -> Self {
+        let container = Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(6)
+            .margin_start(12)
+            .margin_end(12)
+            .margin_bottom(12)
+            .visible(false)
+            .build();
+
+        Self {
+            container,
+            logs: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Append output from `plugin` to its log, highlighting stderr
+    pub fn append(&self, plugin: &str, text: &str, is_stderr: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        let mut logs = self.logs.borrow_mut();
+        let log = logs.entry(plugin.to_string()).or_insert_with(|| {
+            let log = PluginLog::new(plugin);
+            self.container.append(&log.expander);
+            self.container.set_visible(true);
+            log
+        });
+
+        let mut end = log.buffer.end_iter();
+        let text = if text.ends_with('\n') {
+            text.to_string()
+        } else {
+            format!("{}\n", text)
+        };
+        if is_stderr {
+            log.buffer
+                .insert_with_tags_by_name(&mut end, &text, &["stderr"]);
+        } else {
+            log.buffer.insert(&mut end, &text);
+        }
+
+        // Follow the output as it arrives
+        if let Some(mark) = log.buffer.mark("end") {
+            log.view.scroll_mark_onscreen(&mark);
+        }
+    }
+
+    /// Remove the logs of a previous run
+    pub fn clear(&self) {
+        for (_, log) in self.logs.borrow_mut().drain() {
+            self.container.remove(&log.expander);
+        }
+        self.container.set_visible(false);
+    }
+}
+
+impl PluginLog {
+    fn new(plugin: &str) -> Self {
+        let buffer = TextBuffer::new(None);
+        buffer.tag_table().add(
+            &TextTag::builder()
+                .name("stderr")
+                .foreground("#e01b24")
+                .build(),
+        );
+        buffer.tag_table().add(
+            &TextTag::builder()
+                .name("search")
+                .background("#f6d32d")
+                .foreground("#000000")
+                .build(),
+        );
+        // A mark that stays at the end of the buffer, to scroll to
+        buffer.create_mark(Some("end"), &buffer.end_iter(), false);
+
+        let view = TextView::builder()
+            .buffer(&buffer)
+            .editable(false)
+            .cursor_visible(false)
+            .monospace(true)
+            .wrap_mode(gtk::WrapMode::WordChar)
+            .build();
+
+        let scrolled_window = ScrolledWindow::builder()
+            .child(&view)
+            .min_content_height(200)
+            .build();
+
+        let search_entry = SearchEntry::builder()
+            .placeholder_text("Search log")
+            .hexpand(true)
+            .build();
+        search_entry.connect_search_changed(glib::clone!(
+            #[weak]
+            buffer,
+            #[weak]
+            view,
+            move |entry| search(&view, &buffer, &entry.text())
+        ));
+
+        let copy_button = Button::builder()
+            .icon_name("edit-copy-symbolic")
+            .tooltip_text("Copy to clipboard")
+            .build();
+        copy_button.connect_clicked(glib::clone!(
+            #[weak]
+            buffer,
+            move |button| button.clipboard().set_text(&buffer_text(&buffer))
+        ));
+
+        let save_button = Button::builder()
+            .icon_name("document-save-symbolic")
+            .tooltip_text("Save log to file")
+            .build();
+        let file_name = format!("{}.log", plugin);
+        save_button.connect_clicked(glib::clone!(
+            #[weak]
+            buffer,
+            move |button| save(button, &file_name, buffer_text(&buffer))
+        ));
+
+        let toolbar = Box::new(gtk::Orientation::Horizontal, 6);
+        toolbar.append(&search_entry);
+        toolbar.append(&copy_button);
+        toolbar.append(&save_button);
+
+        let child = Box::new(gtk::Orientation::Vertical, 6);
+        child.append(&toolbar);
+        child.append(&scrolled_window);
+
+        let expander = Expander::builder()
+            .label(format!("{} log", plugin))
+            .child(&child)
+            .build();
+
+        Self {
+            expander,
+            view,
+            buffer,
+        }
+    }
+}
+
+fn buffer_text(buffer: &TextBuffer) -> String {
+    buffer
+        .text(&buffer.start_iter(), &buffer.end_iter(), false)
+        .to_string()
+}
+
+/// Highlight every match of `query` in the buffer, and scroll to the first one
+fn search(view: &TextView, buffer: &TextBuffer, query: &str) {
+    buffer.remove_tag_by_name("search", &buffer.start_iter(), &buffer.end_iter());
+    if query.is_empty() {
+        return;
+    }
+
+    let mut first = true;
+    let mut iter = buffer.start_iter();
+    while let Some((mut start, end)) =
+        iter.forward_search(query, gtk::TextSearchFlags::CASE_INSENSITIVE, None)
+    {
+        buffer.apply_tag_by_name("search", &start, &end);
+        if first {
+            view.scroll_to_iter(&mut start, 0.0, false, 0.0, 0.0);
+            first = false;
+        }
+        iter = end;
+    }
+}
+
+/// Ask where to save the log, and write it there
+fn save(button: &Button, file_name: &str, text: String) {
+    let dialog = gtk::FileDialog::builder()
+        .title("Save Log")
+        .initial_name(file_name)
+        .build();
+
+    let window = button.root().and_downcast::<gtk::Window>();
+    dialog.save(window.as_ref(), None::<&gio::Cancellable>, move |result| {
+        if let Ok(file) = result
+            && let Some(path) = file.path()
+            && let Err(err) = std::fs::write(&path, text)
+        {
+            eprintln!("Failed to save log to {}: {}", path.display(), err);
+        }
+    });
+}
@@ -1,55 +1,82 @@
 mod actions;
+mod background;
+mod changelog;
+mod cli;
 mod config;
+mod history;
+mod log_view;
 mod modals;
+mod progress_view;
 mod ui;
+mod unattended;
 mod utils;
 
 use flume::{Receiver, unbounded};
-use renovatio::{Plugin, PluginMetadata, PluginProgress};
+use log_view::LogView;
+use progress_view::ProgressView;
+use renovatio::engine::{EngineEvent, EngineState};
+use renovatio::loader::LoadError;
+use renovatio::{CancellationToken, PendingUpdate, PluginEvent, PluginMetadata, Stream};
+use ui::PendingList;
 
-use gtk::prelude::*;
-use libloading::{Library, Symbol};
+use adw::prelude::*;
 
-// use std::cell::RefCell;
+use std::cell::RefCell;
+use std::rc::Rc;
 // use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 
-type PluginType = unsafe fn() -> *mut dyn Plugin;
-
 fn main() -> glib::ExitCode {
     // Initialize our GSettings schema, if it doesn't exist
     utils::install_gsettings_schema();
 
+    // Older versions saved the paths of the enabled plugins instead of their names
+    utils::migrate_enabled_plugins();
+
+    // Run a command from the terminal instead of opening the window, if one was given
+    let mut args: Vec<String> = std::env::args().collect();
+    if let Some(exit_code) = cli::run(&args) {
+        return exit_code;
+    }
+
+    // Check for updates without opening the window, see the `background` module
+    let background = args.iter().any(|arg| arg == "--background");
+    args.retain(|arg| arg != "--background");
+
     // Create a new application
     let application = adw::Application::builder()
         .application_id(config::APP_ID)
+        .flags(if background {
+            gio::ApplicationFlags::IS_SERVICE
+        } else {
+            gio::ApplicationFlags::empty()
+        })
         .build();
 
-    // Load plugins at startup, in a Vec<PluginMetadata>
-    let mut plugins: Vec<PluginMetadata> = Vec::new();
-    unsafe {
-        let plugin_libraries = utils::find_plugins();
-        for plugin_path in &plugin_libraries {
-            if let Ok(lib) = Library::new(plugin_path) {
-                let result: Result<Symbol<PluginType>, _> = lib.get(b"create_plugin\0");
-                if let Ok(create_plugin) = result {
-                    let plugin_ptr = create_plugin();
-                    let plugin: Box<dyn Plugin> = Box::from_raw(plugin_ptr); // Reclaim ownership
-                    let mut metadata = PluginMetadata::new(plugin);
-                    metadata.path = Some(plugin_path.clone());
-                    plugins.push(metadata);
-                }
-            }
-        }
+    if background {
+        application.connect_startup(background::start);
     }
 
+    // Load plugins at startup, keeping track of the plugins that failed to load
+    // to show in the preferences
+    let (plugins, failed) = utils::installed_plugins();
+
     application.connect_activate(move |app| {
-        let window = build_ui(app, plugins.clone());
+        // Renovatio was opened again, i.e. while checking in the background
+        if let Some(window) = app.active_window() {
+            window.present();
+            return;
+        }
+
+        let window = build_ui(app, plugins.clone(), failed.clone());
 
         // Connect to the "close-request" signal
         window.connect_close_request(move |window| {
-            // If the window closes, quit the application even if there are active sender channels
-            window.application().unwrap().quit();
+            // If the window closes, quit the application even if there are active sender channels,
+            // unless it keeps checking for updates in the background
+            if !background {
+                window.application().unwrap().quit();
+            }
 
             // Return `glib::Propagation::Stop` to prevent the default handler
             // from closing the window, or `glib::Propagation::Proceed` to allow it.
@@ -59,24 +86,42 @@ fn main() -> glib::ExitCode {
     });
 
     // Run the application
-    application.run()
+    application.run_with_args(&args)
 }
 
-fn build_ui(app: &adw::Application, plugins: Vec<PluginMetadata>) -> adw::ApplicationWindow {
+fn build_ui(
+    app: &adw::Application,
+    plugins: Vec<PluginMetadata>,
+    failed: Vec<(String, LoadError)>,
+) -> adw::ApplicationWindow {
     // Create a channel that will be used to send messages from worker threads
-    let (tx, rx): (flume::Sender<PluginProgress>, Receiver<PluginProgress>) = unbounded();
+    let (tx, rx): (flume::Sender<EngineEvent>, Receiver<EngineEvent>) = unbounded();
 
     let header_bar = ui::get_header_bar();
     let update_button = ui::get_update_button();
-    let plugin_progress_bar = ui::get_plugin_progress_bar();
+    let cancel_button = ui::get_cancel_button();
     let total_progress_bar = ui::get_total_progress_bar();
     let apply_check_button = ui::get_apply_check_button();
+    let pending_label = ui::get_pending_label();
+    let pending_list = PendingList::new();
+    let pending_window = ui::get_pending_scrolled_window(&pending_list);
+    let log_view = LogView::new();
+    let progress_view = ProgressView::new();
+    let unattended_banner = ui::get_unattended_banner();
+
+    // The cancellation token of the running update, if any
+    let running: Rc<RefCell<Option<CancellationToken>>> = Rc::new(RefCell::new(None));
 
     // Create cloned references because the closure will capture them
     let tpbar = total_progress_bar.clone();
-    let ppbar = plugin_progress_bar.clone();
+    let progress = progress_view.clone();
     let apply = apply_check_button.clone();
     let update = update_button.clone();
+    let pending = pending_list.clone();
+    let logs = log_view.clone();
+    let cancel = cancel_button.clone();
+    let running_clone = running.clone();
+    let plugins_clone = plugins.clone();
 
     // Clone handles for the closure that will be run in a new thread
     let tx_clone = tx.clone();
@@ -86,152 +131,210 @@ fn build_ui(app: &adw::Application, plugins: Vec<PluginMetadata>) -> adw::Applic
         // Disable the update button and checkbox while running updates
         apply.set_sensitive(false);
         update.set_sensitive(false);
-        ppbar.set_visible(true);
+        pending.set_sensitive(false);
         tpbar.set_visible(true);
-
-        let tx_worker = tx_clone.clone();
+        logs.clear();
+        cancel.set_label("Cancel");
+        cancel.set_sensitive(true);
+        cancel.set_visible(true);
+
+        // List the enabled plugins, in the order they run, before any of them start
+        let enabled = utils::enabled_plugins();
+        let run: Vec<PluginMetadata> =
+            utils::sort_metadata(plugins_clone.clone(), &utils::plugin_order())
+                .into_iter()
+                .filter(|metadata| enabled.contains(&metadata.name))
+                .filter(|metadata| metadata.availability.is_available())
+                .collect();
+        progress.set_plugins(&run);
+        let paths: Vec<String> = run
+            .iter()
+            .filter_map(|metadata| metadata.path.clone())
+            .collect();
+
+        let tx_worker = history::recorder(tx_clone.clone(), false);
+        let selections = pending.selections();
+        let token = CancellationToken::new();
+        running_clone.replace(Some(token.clone()));
 
         thread::spawn(move || {
-            let settings = gio::Settings::new(config::APP_ID);
-
-            // Load the enabled plugin(s)
-            let plugins = settings.get::<Vec<String>>("plugins");
-
-            for plugin in plugins {
-                let tx_plugin = tx_worker.clone();
-
-                unsafe {
-                    // Load the shared library
-                    if let Ok(lib) = Library::new(plugin) {
-                        // Instantiate the plugin
-                        let result: Result<Symbol<PluginType>, _> = lib.get(b"create_plugin\0");
-                        if let Ok(create_plugin) = result {
-                            // Get the plugin object
-                            let plugin_ptr = create_plugin();
-                            let plugin: Box<dyn Plugin> = Box::from_raw(plugin_ptr); // Reclaim ownership
-
-                            println!("Running update for Plugin: {}", plugin.name());
-
-                            // Run the blocking update
-                            if plugin.update(tx_plugin) {
-                                println!("Update successful");
-                            } else {
-                                println!("Update failed");
-                            }
-                        }
-                    }
-                };
-            }
+            // Run the enabled plugin(s)
+            let mut engine = utils::load_engine(&paths, tx_worker);
+            // A refused run is reported as an event
+            let _ = engine.run(&selections, &token);
         });
     });
 
+    // Stop the running update, the engine reports it as finished once the plugin stops
+    let running_clone = running.clone();
+    cancel_button.connect_clicked(move |button| {
+        if let Some(token) = running_clone.borrow().as_ref() {
+            token.cancel();
+            button.set_label("Cancelling...");
+            button.set_sensitive(false);
+        }
+    });
+
+    check_pending_updates(&pending_label, &pending_list, &plugins);
+
     let main_box = ui::get_main_container(
         &header_bar,
-        &update_button,
-        &apply_check_button,
-        &plugin_progress_bar,
-        &total_progress_bar,
+        &[
+            unattended_banner.upcast_ref(),
+            pending_label.upcast_ref(),
+            pending_window.upcast_ref(),
+            update_button.upcast_ref(),
+            cancel_button.upcast_ref(),
+            apply_check_button.upcast_ref(),
+            progress_view.container.upcast_ref(),
+            total_progress_bar.upcast_ref(),
+            log_view.container.upcast_ref(),
+        ],
     );
     let window = ui::get_window(app, "Renovatio", main_box);
 
+    // Don't leave the update running in the background when the window closes
+    let running_clone = running.clone();
+    window.connect_close_request(move |_| {
+        if let Some(token) = running_clone.borrow().as_ref() {
+            token.cancel();
+        }
+        glib::Propagation::Proceed
+    });
+
     // Now that we have the window, connect the menu actions
     actions::set_about(app, &window);
-    actions::set_preferences(app, &window, plugins.clone());
+    actions::set_history(app, &window);
+    actions::set_rollback(app, &window, plugins.clone());
+    actions::set_preferences(app, &window, plugins.clone(), failed);
+    actions::set_update(app, &update_button);
     actions::set_quit(app);
 
     // Present window
     window.present();
 
-    let ppbar_clone = plugin_progress_bar.clone();
+    show_unattended_run(&unattended_banner, &window);
+
+    let progress_clone = progress_view.clone();
     let tpbar_clone = total_progress_bar.clone();
     let apply_clone = apply_check_button.clone();
     let update_clone = update_button.clone();
+    let cancel_clone = cancel_button.clone();
+    let pending_clone = pending_list.clone();
+    let window_clone = window.clone();
+    let log_view_clone = log_view.clone();
 
     // This is called each time GTK is idle (i.e., not processing events).
     // It will run as often as possible but never blocks the main loop.
-    let settings = gio::Settings::new(config::APP_ID);
-    let mut plugin_index = 1;
-
     glib::idle_add_local(move || {
-        // Load the enabled plugin(s)
-        let plugins = settings.get::<Vec<String>>("plugins");
-
-        let plugin_count = plugins.len();
-
         // Even if we don't have a progress update from a plugin,
-        // pulse the progress bar if we have a pulse_step defined.
-        if ppbar_clone.pulse_step() > 0.0 {
-            ppbar_clone.pulse();
-        }
+        // pulse the progress bars that have a pulse_step defined.
+        progress_clone.pulse();
 
         // Try to receive a message. `try_recv` is non‑blocking.
         match rx.try_recv() {
-            Ok(progress) => {
-                // handle stdout/stderr
-                if let Some(stdout) = progress.stdout {
-                    println!("[{}]: {}", progress.name, stdout);
-                }
-                if let Some(stderr) = progress.stderr {
-                    println!("[{}]: {}", progress.name, stderr);
-                }
-
+            Ok(EngineEvent::StateChanged(EngineState::Running {
+                active,
+                finished,
+                total,
+            })) => {
                 let total_status = format!(
-                    "Updating {} ({}/{})...",
-                    progress.name, plugin_index, plugin_count
+                    "Updating {} ({}/{} done)...",
+                    active.join(", "),
+                    finished,
+                    total
                 );
-
-                // Update the UI
                 tpbar_clone.set_text(Some(&total_status));
-                ppbar_clone.set_text(Some(&progress.status));
-
-                if progress.pulse {
-                    println!("Pulsing plugin progress bar");
-                    ppbar_clone.set_pulse_step(0.25);
-                    ppbar_clone.pulse();
-                } else {
-                    ppbar_clone.set_pulse_step(0.0);
-                    ppbar_clone.set_fraction(progress.progress as f64 / 100.0);
-                }
-
-                // TODO: Append stdout and stderr to a `TextView` in the UI
-
-                if progress.progress == 100 {
-                    apply_clone.set_sensitive(true);
-                    update_clone.set_sensitive(true);
-
-                    // If we're done updating the last plugin, update the UI
-                    if plugin_index == plugin_count {
-                        // Disable pulsing and set the bar to 100%
-                        ppbar_clone.set_pulse_step(0.0);
-                        ppbar_clone.set_fraction(1.0);
-
-                        tpbar_clone.set_text(Some("Updates complete!"));
-
-                        tpbar_clone.set_fraction(1.0);
-
-                        // Check to see if we need to reboot
-                        let reboot = apply_clone.is_active();
+                tpbar_clone.set_fraction(finished as f64 / total as f64);
+                glib::ControlFlow::Continue
+            }
+            Ok(EngineEvent::StateChanged(EngineState::Finished(results))) => {
+                apply_clone.set_sensitive(true);
+                update_clone.set_sensitive(true);
+                pending_clone.set_sensitive(true);
+                cancel_clone.set_visible(false);
+                running.replace(None);
+
+                tpbar_clone.set_fraction(1.0);
+
+                // Check to see if we need to reboot, unless the update was cancelled
+                let cancelled = results.iter().any(|result| result.cancelled);
+                let reboot = apply_clone.is_active() && !cancelled;
+
+                let failed = results
+                    .iter()
+                    .filter(|result| !result.success && !result.cancelled)
+                    .count();
+                let msg = format!(
+                    "{} {}",
+                    if cancelled {
+                        "Updates cancelled.".to_string()
+                    } else if failed == 0 {
+                        "Updates complete!".to_string()
+                    } else {
+                        format!("Updates complete, {} failed.", failed)
+                    },
+                    if reboot { "Rebooting..." } else { "" }
+                );
 
-                        let msg = format!(
-                            "Updates complete! {}",
-                            if reboot { "Rebooting..." } else { "" }
-                        );
+                tpbar_clone.set_text(Some(&msg));
 
-                        tpbar_clone.set_text(Some(&msg));
+                // Show what each plugin did
+                modals::results::show(&window_clone, &results);
 
-                        if reboot && utils::check_reboot_needed() {
-                            std::thread::sleep(std::time::Duration::from_secs(3));
-                            utils::reboot_system();
-                        }
-                    } else {
-                        plugin_index += 1;
-                    }
+                if reboot && utils::check_reboot_needed() {
+                    std::thread::sleep(std::time::Duration::from_secs(3));
+                    utils::reboot_system();
                 }
-
-                // Return Continue if you want to keep listening,
-                // or Stop if this idle callback should run only once.
                 glib::ControlFlow::Continue
             }
+            Ok(EngineEvent::Plugin {
+                plugin,
+                event: PluginEvent::Log { stream, line },
+            }) => {
+                // Append stdout and stderr to the plugin's log
+                log_view_clone.append(&plugin, &line, stream == Stream::Stderr);
+                glib::ControlFlow::Continue
+            }
+            Ok(EngineEvent::Plugin { plugin, event }) => {
+                // Update the plugin's row
+                progress_clone.handle(&plugin, &event);
+                glib::ControlFlow::Continue
+            }
+            Ok(EngineEvent::PluginFinished(result)) => {
+                println!(
+                    "Update {} for Plugin: {}",
+                    if result.success {
+                        "successful"
+                    } else {
+                        "failed"
+                    },
+                    result.name
+                );
+                glib::ControlFlow::Continue
+            }
+            Ok(EngineEvent::LoadFailed { path, error }) => {
+                eprintln!("Failed to load plugin {}: {}", path, error);
+                glib::ControlFlow::Continue
+            }
+            Ok(EngineEvent::Refused(error)) => {
+                apply_clone.set_sensitive(true);
+                update_clone.set_sensitive(true);
+                pending_clone.set_sensitive(true);
+                cancel_clone.set_visible(false);
+                running.replace(None);
+                progress_clone.clear();
+                tpbar_clone.set_visible(false);
+
+                let dialog = adw::AlertDialog::builder()
+                    .heading("Can't Start the Update")
+                    .body(error.to_string())
+                    .build();
+                dialog.add_response("close", "Close");
+                dialog.present(Some(&window_clone));
+                glib::ControlFlow::Continue
+            }
+            Ok(_) => glib::ControlFlow::Continue,
             Err(flume::TryRecvError::Empty) => {
                 // No messages, but there are sender(s) alive – keep the idle handler alive
                 glib::ControlFlow::Continue
@@ -246,3 +349,75 @@ fn build_ui(app: &adw::Application, plugins: Vec<PluginMetadata>) -> adw::Applic
 
     window
 }
+
+/// Say what happened while updating unattended, if the user hasn't seen it yet
+fn show_unattended_run(banner: &adw::Banner, window: &adw::ApplicationWindow) {
+    let Some(run) = history::unseen_unattended_run() else {
+        return;
+    };
+
+    let started = run
+        .started()
+        .and_then(|started| started.format("%A %H:%M").ok())
+        .unwrap_or_default();
+    banner.set_title(&format!(
+        "Updated automatically on {}: {}",
+        started,
+        run.summary()
+    ));
+    banner.set_revealed(true);
+
+    banner.connect_button_clicked(glib::clone!(
+        #[weak]
+        window,
+        move |banner| {
+            history::mark_seen(&run);
+            banner.set_revealed(false);
+            modals::results::show(&window, &run.results);
+        }
+    ));
+}
+
+/// Ask each enabled plugin for its pending updates, summarize them in `label`
+/// and list them in `pending_list`
+fn check_pending_updates(
+    label: &gtk::Label,
+    pending_list: &PendingList,
+    plugins: &[PluginMetadata],
+) {
+    let (tx, rx) = unbounded::<EngineEvent>();
+
+    thread::spawn(move || {
+        let mut engine = utils::load_engine(&utils::plugin_paths(&utils::enabled_plugins()), tx);
+        engine.check();
+    });
+
+    let label = label.clone();
+    let pending_list = pending_list.clone();
+
+    // The plugins that can show the release notes of their updates
+    let changelogs: Vec<String> = plugins
+        .iter()
+        .filter(|metadata| metadata.capabilities.changelog)
+        .map(|metadata| metadata.name.clone())
+        .collect();
+    let mut pending: Vec<(String, Vec<PendingUpdate>)> = Vec::new();
+
+    glib::idle_add_local(move || match rx.try_recv() {
+        Ok(EngineEvent::Checked { plugin, updates }) => {
+            pending_list.add_plugin(&plugin, &updates, changelogs.contains(&plugin));
+            pending.push((plugin, updates));
+            label.set_text(&ui::pending_summary(&pending));
+            glib::ControlFlow::Continue
+        }
+        Ok(_) => glib::ControlFlow::Continue,
+        Err(flume::TryRecvError::Empty) => glib::ControlFlow::Continue,
+        Err(flume::TryRecvError::Disconnected) => {
+            // The check is complete
+            if pending.iter().all(|(_, updates)| updates.is_empty()) {
+                label.set_text("No pending updates found");
+            }
+            glib::ControlFlow::Break
+        }
+    });
+}
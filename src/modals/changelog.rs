@@ -0,0 +1,74 @@
+// A modal showing what's new in a pending update, i.e. its release notes or the packages it changes.
+use crate::changelog;
+
+use adw::prelude::*;
+use renovatio::PendingUpdate;
+
+use std::thread;
+
+pub fn show(parent: &impl IsA<gtk::Widget>, plugin: &str, update: &PendingUpdate) {
+    let stack = gtk::Stack::new();
+    stack.add_named(&adw::Spinner::new(), Some("loading"));
+
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+    toolbar_view.set_content(Some(&stack));
+
+    let dialog = adw::Dialog::builder()
+        .title(format!("What's New in {}", update.name))
+        .content_width(450)
+        .content_height(500)
+        .child(&toolbar_view)
+        .build();
+
+    dialog.present(Some(parent));
+
+    // Asking the plugin can take a while, unless the release notes are cached
+    let (tx, rx) = flume::bounded::<Option<String>>(1);
+    let (plugin, update) = (plugin.to_string(), update.clone());
+    thread::spawn(move || {
+        let _ = tx.send(changelog::fetch(&plugin, &update));
+    });
+
+    glib::spawn_future_local(glib::clone!(
+        #[weak]
+        stack,
+        async move {
+            let Ok(changelog) = rx.recv_async().await else {
+                return;
+            };
+            let content: gtk::Widget = match changelog {
+                Some(changelog) => notes(&changelog).upcast(),
+                None => adw::StatusPage::builder()
+                    .icon_name("dialog-information-symbolic")
+                    .title("No Release Notes")
+                    .description("The plugin couldn't say what's new in this update")
+                    .build()
+                    .upcast(),
+            };
+            stack.add_named(&content, Some("changelog"));
+            stack.set_visible_child(&content);
+        }
+    ));
+}
+
+/// The release notes, which may be package lists that line up in columns
+fn notes(changelog: &str) -> gtk::ScrolledWindow {
+    let label = gtk::Label::builder()
+        .label(changelog)
+        .selectable(true)
+        .wrap(true)
+        .xalign(0.0)
+        .yalign(0.0)
+        .css_classes(["monospace"])
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+
+    gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .child(&label)
+        .build()
+}
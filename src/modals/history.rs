@@ -0,0 +1,192 @@
+// A modal listing the past update runs, to look up when something was updated and how it went.
+use crate::history::{self, Run};
+use crate::modals::results;
+
+use adw::prelude::*;
+use adw::{
+    ActionRow, ExpanderRow, NavigationPage, NavigationView, PreferencesGroup, PreferencesPage,
+};
+
+use std::rc::Rc;
+
+pub fn show(parent: &impl IsA<gtk::Widget>) {
+    // Newest first
+    let runs: Rc<Vec<Run>> = Rc::new(history::load().into_iter().rev().collect());
+
+    let navigation = NavigationView::new();
+    navigation.add(&list_page(&navigation, &runs));
+
+    let dialog = adw::Dialog::builder()
+        .title("History")
+        .content_width(450)
+        .content_height(500)
+        .child(&navigation)
+        .build();
+
+    dialog.present(Some(parent));
+}
+
+/// The page listing every run, which can be searched for plugins and items
+fn list_page(navigation: &NavigationView, runs: &Rc<Vec<Run>>) -> NavigationPage {
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+
+    if runs.is_empty() {
+        let status = adw::StatusPage::builder()
+            .icon_name("document-open-recent-symbolic")
+            .title("No Updates Yet")
+            .description(format!(
+                "Updates are recorded in {}",
+                glib::markup_escape_text(&history::path().display().to_string())
+            ))
+            .build();
+        toolbar_view.set_content(Some(&status));
+        return NavigationPage::builder()
+            .title("History")
+            .child(&toolbar_view)
+            .build();
+    }
+
+    let search = gtk::SearchEntry::builder()
+        .placeholder_text("Search plugins and updates")
+        .build();
+
+    let list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(["boxed-list"])
+        .build();
+    for run in runs.iter() {
+        list.append(&run_row(run));
+    }
+
+    // Open a run's page when its row is activated
+    list.connect_row_activated(glib::clone!(
+        #[weak]
+        navigation,
+        #[strong]
+        runs,
+        move |_, row| {
+            if let Some(run) = runs.get(row.index() as usize) {
+                navigation.push(&run_page(run));
+            }
+        }
+    ));
+
+    // Only list the runs matching the search
+    list.set_filter_func(glib::clone!(
+        #[weak]
+        search,
+        #[strong]
+        runs,
+        #[upgrade_or]
+        true,
+        move |row| {
+            let text = search.text();
+            text.is_empty()
+                || runs
+                    .get(row.index() as usize)
+                    .is_some_and(|run| run.matches(&text))
+        }
+    ));
+    search.connect_search_changed(glib::clone!(
+        #[weak]
+        list,
+        move |_| list.invalidate_filter()
+    ));
+
+    let search_group = PreferencesGroup::new();
+    search_group.add(&search);
+    let runs_group = PreferencesGroup::new();
+    runs_group.add(&list);
+
+    let page = PreferencesPage::new();
+    page.add(&search_group);
+    page.add(&runs_group);
+    toolbar_view.set_content(Some(&page));
+
+    NavigationPage::builder()
+        .title("History")
+        .child(&toolbar_view)
+        .build()
+}
+
+/// A row summarizing a run, i.e. "12 Oct 2026, 02:10" and "12 updated: flatpak, brew"
+fn run_row(run: &Run) -> ActionRow {
+    let plugins: Vec<&str> = run
+        .results
+        .iter()
+        .map(|result| result.name.as_str())
+        .collect();
+
+    let row = ActionRow::builder()
+        .title(started(run))
+        .subtitle(format!("{}: {}", run.summary(), plugins.join(", ")))
+        .use_markup(false)
+        .activatable(true)
+        .build();
+    if run.unattended {
+        row.add_prefix(&gtk::Image::from_icon_name("alarm-symbolic"));
+    }
+    row.add_suffix(&gtk::Image::from_icon_name("go-next-symbolic"));
+    row
+}
+
+/// The page showing how each plugin's update went during `run`, and its output
+fn run_page(run: &Run) -> NavigationPage {
+    let page = results::page(&run.results);
+
+    if !run.logs.is_empty() {
+        let output = PreferencesGroup::builder().title("Output").build();
+        for result in &run.results {
+            let lines: Vec<&str> = run
+                .logs_of(&result.name)
+                .map(|log| log.line.as_str())
+                .collect();
+            if !lines.is_empty() {
+                output.add(&output_row(&result.name, &lines));
+            }
+        }
+        page.add(&output);
+    }
+
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+    toolbar_view.set_content(Some(&page));
+
+    NavigationPage::builder()
+        .title(started(run))
+        .child(&toolbar_view)
+        .build()
+}
+
+/// An expandable row showing a plugin's output
+fn output_row(plugin: &str, lines: &[&str]) -> ExpanderRow {
+    let row = ExpanderRow::builder()
+        .title(plugin)
+        .subtitle(format!("{} lines", lines.len()))
+        .use_markup(false)
+        .build();
+
+    let label = gtk::Label::builder()
+        .label(lines.join("\n"))
+        .selectable(true)
+        .wrap(true)
+        .xalign(0.0)
+        .css_classes(["monospace"])
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    row.add_row(&label);
+
+    row
+}
+
+/// When `run` started, i.e. "12 Oct 2026, 02:10"
+fn started(run: &Run) -> String {
+    run.started()
+        .and_then(|started| started.format("%e %b %Y, %H:%M").ok())
+        .map(|started| started.trim().to_string())
+        .unwrap_or_default()
+}
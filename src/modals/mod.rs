@@ -1,2 +1,6 @@
 pub mod about;
+pub mod changelog;
+pub mod history;
 pub mod preferences;
+pub mod results;
+pub mod rollback;
@@ -1,10 +1,25 @@
 // A preferences modal that allows the user to configure the application's settings.
-use crate::{PluginMetadata, config};
+use crate::{LoadError, PluginMetadata, background, config, unattended, utils};
 
 use adw::prelude::*;
-use adw::{PreferencesDialog, PreferencesGroup, PreferencesPage, SwitchRow};
+use adw::{
+    ActionRow, AlertDialog, PreferencesDialog, PreferencesGroup, PreferencesPage,
+    ResponseAppearance, SpinRow, SwitchRow,
+};
 
-pub fn show(parent: &impl IsA<gtk::Widget>, plugins: Vec<PluginMetadata>) {
+use gtk::gdk;
+use renovatio::Privileges;
+use renovatio::settings::{PluginSettings, SettingKind, SettingValue};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub fn show(
+    parent: &impl IsA<gtk::Widget>,
+    plugins: Vec<PluginMetadata>,
+    failed: &[(String, LoadError)],
+) {
     let dialog = PreferencesDialog::builder()
         // .transient_for(&parent) // Make it a transient dialog for the main window
         // .modal(true) // Make it a modal dialog
@@ -17,42 +32,90 @@ pub fn show(parent: &impl IsA<gtk::Widget>, plugins: Vec<PluginMetadata>) {
 
     let group1 = PreferencesGroup::builder().title("Plugins").build();
 
+    // Keep track of each plugin's switch, so a conflicting plugin can be switched off. The
+    // switches' handlers hold on to the map, so it only refers to them weakly.
+    let rows: HashMap<String, SwitchRow> = plugins
+        .iter()
+        .map(|metadata| {
+            let row = SwitchRow::builder()
+                .title(&metadata.name)
+                .subtitle(&metadata.description)
+                .use_markup(false)
+                .build();
+            row.add_prefix(&gtk::Image::from_icon_name(&metadata.icon_name));
+
+            // Grey out plugins that can't run here, saying why
+            if let Some(reason) = metadata.availability.reason() {
+                row.set_subtitle(reason);
+                row.set_sensitive(false);
+            }
+            (metadata.name.clone(), row)
+        })
+        .collect();
+    let weak_rows: Rc<HashMap<String, glib::WeakRef<SwitchRow>>> = Rc::new(
+        rows.iter()
+            .map(|(name, row)| (name.clone(), row.downgrade()))
+            .collect(),
+    );
+
+    let order_group = run_order_group(&plugins);
+
     for metadata in plugins {
-        let switch_row = SwitchRow::builder()
-            .title(&metadata.name)
-            .subtitle(&metadata.description)
+        let switch_row = rows[&metadata.name].clone();
+
+        // Open the plugin's about page
+        let about_button = gtk::Button::builder()
+            .icon_name("help-about-symbolic")
+            .tooltip_text("About this plugin and its settings")
+            .valign(gtk::Align::Center)
+            .css_classes(["flat"])
             .build();
+        about_button.connect_clicked(glib::clone!(
+            #[weak]
+            dialog,
+            #[strong]
+            metadata,
+            move |_| dialog.push_subpage(&plugin_page(&metadata))
+        ));
+        switch_row.add_suffix(&about_button);
 
         let settings = gio::Settings::new(config::APP_ID);
 
-        // get the current plugins key
-        let plugins = settings.get::<Vec<String>>("plugins");
-        if let Some(path) = metadata.path.clone() {
-            let plugin = plugins.contains(&path);
-            if plugin {
-                // Set the row to active if the plugin is enabled
-                switch_row.set_active(true);
-            }
+        // Set the row to active if the plugin is enabled
+        let plugins = settings.get::<Vec<String>>("enabled-plugins");
+        if plugins.contains(&metadata.name) {
+            switch_row.set_active(true);
+
+            // ...and still let it be switched off if it can't run here anymore
+            switch_row.set_sensitive(true);
         }
 
         // Connect a signal to the switch
+        let rows = weak_rows.clone();
         switch_row.connect_active_notify(move |state| {
-            let mut plugins = settings.get::<Vec<String>>("plugins");
+            // Ask before enabling a plugin alongside one it conflicts with
+            if state.is_active() {
+                let conflicting: Vec<&String> = metadata
+                    .conflicts
+                    .iter()
+                    .filter(|name| {
+                        rows.get(*name)
+                            .and_then(|row| row.upgrade())
+                            .is_some_and(|row| row.is_active())
+                    })
+                    .collect();
+                if !conflicting.is_empty() {
+                    confirm_conflict(state, &metadata, &conflicting, &rows);
+                    return;
+                }
+            }
 
-            // get the path for the current plugin
-            let plugin_path = metadata.path.clone().unwrap();
+            save_enabled(&metadata, state.is_active());
 
-            // append or remove the current plugin
-            if state.is_active() {
-                // append the plugin to the list
-                plugins.push(plugin_path);
-            } else {
-                // remove the plugin from the list
-                plugins.retain(|path| !path.contains(&plugin_path));
+            // An unavailable plugin can't be switched back on
+            if !state.is_active() && !metadata.availability.is_available() {
+                state.set_sensitive(false);
             }
-            println!("Saving plugins: {:?}", plugins);
-            // save the changes to gsettings
-            let _ = settings.set("plugins", &plugins);
 
             // Handle the state change here
             // println!("{} is active: {}", metadata.name, state.is_active());
@@ -63,7 +126,497 @@ pub fn show(parent: &impl IsA<gtk::Widget>, plugins: Vec<PluginMetadata>) {
 
     // group1.add(&row1);
     page1.add(&group1);
+    page1.add(&order_group);
+    page1.add(&updates_group());
+
+    // List the plugins that couldn't be loaded, and why
+    if !failed.is_empty() {
+        let group2 = PreferencesGroup::builder()
+            .title("Incompatible Plugins")
+            .description("These plugins could not be loaded.")
+            .build();
+
+        for (path, error) in failed {
+            let row = ActionRow::builder()
+                .title(path.rsplit('/').next().unwrap_or(path))
+                .subtitle(error.to_string())
+                .use_markup(false)
+                .tooltip_text(path)
+                .build();
+            row.add_prefix(&gtk::Image::from_icon_name("dialog-error-symbolic"));
+            group2.add(&row);
+        }
+        page1.add(&group2);
+    }
     dialog.add(&page1);
 
     dialog.present(Some(parent));
 }
+
+/// A list of the plugins, in the order they run, that can be dragged to reorder them.
+/// The order is saved as the user's override of the order the plugins declare.
+fn run_order_group(plugins: &[PluginMetadata]) -> PreferencesGroup {
+    let list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(["boxed-list"])
+        .build();
+
+    // The order the plugins declare, which a manual order replaces
+    let declared: Rc<Vec<String>> = Rc::new(
+        utils::sort_metadata(plugins.to_vec(), &[])
+            .into_iter()
+            .map(|metadata| metadata.name)
+            .collect(),
+    );
+
+    for metadata in plugins {
+        list.append(&run_order_row(&list, &metadata.name, &declared));
+    }
+
+    // Go back to the order the plugins declare
+    let reset_button = gtk::Button::builder()
+        .label("Reset")
+        .css_classes(["flat"])
+        .valign(gtk::Align::Center)
+        .build();
+    let list_clone = list.clone();
+    reset_button.connect_clicked(move |_| {
+        list_clone.remove_all();
+        for name in declared.iter() {
+            list_clone.append(&run_order_row(&list_clone, name, &declared));
+        }
+
+        let settings = gio::Settings::new(config::APP_ID);
+        let _ = settings.set("plugin-order", Vec::<String>::new());
+    });
+
+    let group = PreferencesGroup::builder()
+        .title("Run Order")
+        .description(
+            "Drag the plugins to change the order they run in. Your order replaces the one \
+             the plugins ask for, even when they're updated, until you reset it.",
+        )
+        .header_suffix(&reset_button)
+        .build();
+    group.add(&list);
+    group
+}
+
+/// A page describing a plugin: who made it, what it can do, what it needs and its settings
+fn plugin_page(metadata: &PluginMetadata) -> adw::NavigationPage {
+    let page = PreferencesPage::builder()
+        .title(&metadata.name)
+        .description(glib::markup_escape_text(&metadata.description))
+        .build();
+
+    let group = PreferencesGroup::new();
+    let property = |title: &str, value: &str| {
+        let row = ActionRow::builder()
+            .title(title)
+            .subtitle(value)
+            .subtitle_selectable(true)
+            .use_markup(false)
+            .css_classes(["property"])
+            .build();
+        group.add(&row);
+    };
+
+    property("Version", &metadata.version);
+    if !metadata.author.is_empty() {
+        property("Author", &metadata.author);
+    }
+    if let Some(path) = &metadata.path {
+        property("Location", path);
+    }
+    property(
+        "Privileges",
+        match metadata.privileges {
+            Privileges::User => "Updates your own software",
+            Privileges::Administrator => {
+                "Asks for an administrator's password to update the system"
+            }
+        },
+    );
+    if let Some(reason) = metadata.availability.reason() {
+        property("Unavailable", reason);
+    }
+    if !metadata.conflicts.is_empty() {
+        property("Conflicts With", &metadata.conflicts.join(", "));
+    }
+    if !metadata.homepage.is_empty() {
+        let row = ActionRow::builder()
+            .title("Homepage")
+            .subtitle(&metadata.homepage)
+            .use_markup(false)
+            .activatable(true)
+            .build();
+        row.add_suffix(&gtk::Image::from_icon_name("adw-external-link-symbolic"));
+        let homepage = metadata.homepage.clone();
+        row.connect_activated(move |row| {
+            gtk::UriLauncher::new(&homepage).launch(
+                row.root().and_downcast_ref::<gtk::Window>(),
+                gio::Cancellable::NONE,
+                |_| {},
+            );
+        });
+        group.add(&row);
+    }
+    page.add(&group);
+
+    if !metadata.settings.is_empty() {
+        page.add(&plugin_settings_group(metadata));
+    }
+
+    // What the plugin can do besides applying every update
+    let capabilities = PreferencesGroup::builder().title("Capabilities").build();
+    for (title, supported) in [
+        ("Check for Updates", metadata.capabilities.check),
+        (
+            "Choose Which Updates to Apply",
+            metadata.capabilities.selective_update,
+        ),
+        ("Roll Back", metadata.capabilities.rollback),
+        ("Show What's New", metadata.capabilities.changelog),
+        (
+            "Check in the Background",
+            metadata.can_check_in_background(),
+        ),
+    ] {
+        let row = ActionRow::builder().title(title).build();
+        row.add_suffix(&gtk::Image::from_icon_name(if supported {
+            "emblem-ok-symbolic"
+        } else {
+            "action-unavailable-symbolic"
+        }));
+        capabilities.add(&row);
+    }
+    page.add(&capabilities);
+
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+    toolbar_view.set_content(Some(&page));
+
+    adw::NavigationPage::builder()
+        .title(&metadata.name)
+        .child(&toolbar_view)
+        .build()
+}
+
+/// A row for each of the settings a plugin declares, saving changes to the plugin's settings
+fn plugin_settings_group(metadata: &PluginMetadata) -> PreferencesGroup {
+    let group = PreferencesGroup::builder().title("Settings").build();
+    let saved = PluginSettings::load(&metadata.name, &metadata.settings);
+
+    let values = RefCell::new(saved.clone());
+    let name = metadata.name.clone();
+    let save = Rc::new(move |key: &str, value: SettingValue| {
+        let mut values = values.borrow_mut();
+        values.set(key, value);
+        if let Err(error) = values.save(&name) {
+            eprintln!("Failed to save the settings of {}: {}", name, error);
+        }
+    });
+
+    for setting in &metadata.settings {
+        let key = setting.key.clone();
+        let save = save.clone();
+
+        let row: gtk::Widget = match &setting.kind {
+            SettingKind::Bool { .. } => {
+                let row = SwitchRow::builder()
+                    .title(&setting.title)
+                    .subtitle(&setting.description)
+                    .use_markup(false)
+                    .active(saved.bool(&key))
+                    .build();
+                row.connect_active_notify(move |row| {
+                    save(&key, SettingValue::Bool(row.is_active()))
+                });
+                row.upcast()
+            }
+            SettingKind::Choice { choices, .. } => {
+                let model =
+                    gtk::StringList::new(&choices.iter().map(String::as_str).collect::<Vec<_>>());
+                let row = adw::ComboRow::builder()
+                    .title(&setting.title)
+                    .subtitle(&setting.description)
+                    .use_markup(false)
+                    .model(&model)
+                    .build();
+                if let Some(index) = choices
+                    .iter()
+                    .position(|choice| choice == saved.choice(&key))
+                {
+                    row.set_selected(index as u32);
+                }
+                let choices = choices.clone();
+                row.connect_selected_notify(move |row| {
+                    if let Some(choice) = choices.get(row.selected() as usize) {
+                        save(&key, SettingValue::String(choice.clone()));
+                    }
+                });
+                row.upcast()
+            }
+            SettingKind::List { .. } => {
+                // Entry rows have no subtitle, so the description is the tooltip
+                let row = adw::EntryRow::builder()
+                    .title(&setting.title)
+                    .text(saved.list(&key).join(", "))
+                    .use_markup(false)
+                    .tooltip_text(&setting.description)
+                    .show_apply_button(true)
+                    .build();
+                row.connect_apply(move |row| {
+                    let items = row
+                        .text()
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    save(&key, SettingValue::List(items));
+                });
+                row.upcast()
+            }
+        };
+        group.add(&row);
+    }
+
+    group
+}
+
+/// Settings for how updates run
+fn updates_group() -> PreferencesGroup {
+    let settings = gio::Settings::new(config::APP_ID);
+
+    let parallel_row = SpinRow::builder()
+        .title("Parallel Updates")
+        .subtitle("The number of plugins that can update at once")
+        .adjustment(&gtk::Adjustment::new(1.0, 1.0, 8.0, 1.0, 1.0, 0.0))
+        .build();
+    settings
+        .bind("max-parallel", &parallel_row, "value")
+        .build();
+
+    let background_row = SwitchRow::builder()
+        .title("Check in the Background")
+        .subtitle("Check for updates after logging in, and notify when there are some")
+        .active(background::is_autostarted())
+        .build();
+    background_row.connect_active_notify(|row| {
+        if let Err(error) = background::set_autostart(row.is_active()) {
+            eprintln!("Failed to change the background checks: {}", error);
+        }
+    });
+
+    let interval_row = SpinRow::builder()
+        .title("Check Interval")
+        .subtitle("The number of hours between checks in the background")
+        .adjustment(&gtk::Adjustment::new(6.0, 1.0, 168.0, 1.0, 6.0, 0.0))
+        .build();
+    settings
+        .bind("check-interval", &interval_row, "value")
+        .build();
+    background_row
+        .bind_property("active", &interval_row, "sensitive")
+        .sync_create()
+        .build();
+
+    let group = PreferencesGroup::builder().title("Updates").build();
+    group.add(&parallel_row);
+    group.add(&background_row);
+    group.add(&interval_row);
+    unattended_rows(&group, &background_row);
+    group
+}
+
+/// Add the rows for updating unattended during the maintenance window to `group`
+fn unattended_rows(group: &PreferencesGroup, background_row: &SwitchRow) {
+    let settings = gio::Settings::new(config::APP_ID);
+
+    let unattended_row = SwitchRow::builder()
+        .title("Update Automatically")
+        .subtitle("Apply the updates during the maintenance window, on AC power and unmetered networks. Plugins that need an administrator's password, like bootc, are left out.")
+        .build();
+    settings
+        .bind("unattended-updates", &unattended_row, "active")
+        .build();
+
+    // Updates are applied by the background checks, so they have to be on
+    unattended_row.connect_active_notify(glib::clone!(
+        #[weak]
+        background_row,
+        move |row| {
+            if row.is_active() {
+                background_row.set_active(true);
+            }
+        }
+    ));
+
+    let day_row = adw::ComboRow::builder()
+        .title("Maintenance Day")
+        .model(&gtk::StringList::new(unattended::DAYS))
+        .selected(settings.int("maintenance-day").clamp(0, 7) as u32)
+        .build();
+    day_row.connect_selected_notify(|row| {
+        let settings = gio::Settings::new(config::APP_ID);
+        let _ = settings.set_int("maintenance-day", row.selected() as i32);
+    });
+
+    let hour_row = |title: &str, key: &str| {
+        let row = SpinRow::builder()
+            .title(title)
+            .subtitle("The hour of the day, from 0 to 23")
+            .adjustment(&gtk::Adjustment::new(0.0, 0.0, 23.0, 1.0, 1.0, 0.0))
+            .build();
+        settings.bind(key, &row, "value").build();
+        row
+    };
+    let start_row = hour_row("Maintenance Window Starts", "maintenance-start");
+    let end_row = hour_row("Maintenance Window Ends", "maintenance-end");
+
+    group.add(&unattended_row);
+    for row in [
+        day_row.upcast_ref::<gtk::Widget>(),
+        start_row.upcast_ref(),
+        end_row.upcast_ref(),
+    ] {
+        unattended_row
+            .bind_property("active", row, "sensitive")
+            .sync_create()
+            .build();
+        group.add(row);
+    }
+}
+
+/// A row for `plugin` that can be dragged onto another row in `list` to take its place.
+/// Only an order that differs from the `declared` one is saved.
+fn run_order_row(list: &gtk::ListBox, plugin: &str, declared: &Rc<Vec<String>>) -> ActionRow {
+    let row = ActionRow::builder()
+        .title(plugin)
+        .name(plugin)
+        .use_markup(false)
+        .build();
+    row.add_prefix(&gtk::Image::from_icon_name("list-drag-handle-symbolic"));
+
+    let drag_source = gtk::DragSource::builder()
+        .actions(gdk::DragAction::MOVE)
+        .build();
+    let name = plugin.to_string();
+    drag_source
+        .connect_prepare(move |_, _, _| Some(gdk::ContentProvider::for_value(&name.to_value())));
+    row.add_controller(drag_source);
+
+    let drop_target = gtk::DropTarget::new(String::static_type(), gdk::DragAction::MOVE);
+    let list = list.clone();
+    let target = row.clone();
+    let declared = declared.clone();
+    drop_target.connect_drop(move |_, value, _, _| {
+        let Ok(dragged) = value.get::<String>() else {
+            return false;
+        };
+        let Some(dragged_row) = run_order_rows(&list)
+            .into_iter()
+            .find(|row| row.widget_name() == dragged)
+        else {
+            return false;
+        };
+
+        // Move the dragged row into the target's place
+        let index = target.index();
+        list.remove(&dragged_row);
+        list.insert(&dragged_row, index);
+
+        let order: Vec<String> = run_order_rows(&list)
+            .iter()
+            .map(|row| row.widget_name().to_string())
+            .collect();
+        // Dragging the plugins back into the declared order goes back to following it
+        let order = if order == *declared {
+            Vec::new()
+        } else {
+            order
+        };
+        let settings = gio::Settings::new(config::APP_ID);
+        let _ = settings.set("plugin-order", &order);
+        true
+    });
+    row.add_controller(drop_target);
+
+    row
+}
+
+/// The rows of the run order list, in order
+fn run_order_rows(list: &gtk::ListBox) -> Vec<gtk::ListBoxRow> {
+    (0..).map_while(|index| list.row_at_index(index)).collect()
+}
+
+/// Add or remove a plugin from the enabled plugins
+fn save_enabled(metadata: &PluginMetadata, enabled: bool) {
+    let settings = gio::Settings::new(config::APP_ID);
+    let mut plugins = settings.get::<Vec<String>>("enabled-plugins");
+
+    // append or remove the current plugin
+    if enabled {
+        // append the plugin to the list
+        if !plugins.contains(&metadata.name) {
+            plugins.push(metadata.name.clone());
+        }
+    } else {
+        // remove the plugin from the list
+        plugins.retain(|name| name != &metadata.name);
+    }
+    println!("Saving plugins: {:?}", plugins);
+    // save the changes to gsettings
+    let _ = settings.set("enabled-plugins", &plugins);
+}
+
+/// Explain that enabling `plugin` conflicts with the `conflicting` plugins, offering to
+/// disable them. The plugin is switched back off if the user keeps them instead.
+fn confirm_conflict(
+    row: &SwitchRow,
+    plugin: &PluginMetadata,
+    conflicting: &[&String],
+    rows: &Rc<HashMap<String, glib::WeakRef<SwitchRow>>>,
+) {
+    let names = conflicting
+        .iter()
+        .map(|name| name.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+
+    let dialog = AlertDialog::builder()
+        .heading("Conflicting Plugins")
+        .body(format!(
+            "{} conflicts with {}, which is enabled. They update the same things, so only one of them can run.",
+            plugin.name, names
+        ))
+        .build();
+    dialog.add_responses(&[
+        ("keep", &format!("Keep {}", names)),
+        ("disable", &format!("Disable {}", names)),
+    ]);
+    dialog.set_response_appearance("disable", ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("keep"));
+    dialog.set_close_response("keep");
+
+    let row_clone = row.clone();
+    let plugin = plugin.clone();
+    let rows = rows.clone();
+    let conflicting: Vec<String> = conflicting.iter().map(|name| name.to_string()).collect();
+    dialog.connect_response(None, move |_, response| {
+        if response == "disable" {
+            // Switching the conflicting plugins off saves them as disabled
+            for name in &conflicting {
+                if let Some(other) = rows.get(name).and_then(|row| row.upgrade()) {
+                    other.set_active(false);
+                }
+            }
+            save_enabled(&plugin, true);
+        } else {
+            row_clone.set_active(false);
+        }
+    });
+
+    dialog.present(Some(row));
+}
@@ -0,0 +1,128 @@
+// A modal summarizing how each plugin's update went, after an update run.
+use crate::utils;
+
+use adw::prelude::*;
+use adw::{ActionRow, ExpanderRow, PreferencesGroup, PreferencesPage};
+use renovatio::engine::PluginResult;
+use renovatio::{ItemResult, PluginError};
+
+pub fn show(parent: &impl IsA<gtk::Widget>, results: &[PluginResult]) {
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+    toolbar_view.set_content(Some(&page(results)));
+
+    let dialog = adw::Dialog::builder()
+        .title("Update Results")
+        .content_width(450)
+        .content_height(500)
+        .child(&toolbar_view)
+        .build();
+
+    dialog.present(Some(parent));
+}
+
+/// A page with a group for each plugin, listing how its items' updates went
+pub fn page(results: &[PluginResult]) -> PreferencesPage {
+    let page = PreferencesPage::new();
+
+    for result in results {
+        let group = PreferencesGroup::builder()
+            .title(glib::markup_escape_text(&result.name))
+            .description(outcome(result))
+            .build();
+        if result.cancelled {
+            group.set_header_suffix(Some(&gtk::Image::from_icon_name("process-stop-symbolic")));
+        } else {
+            group.set_header_suffix(Some(&status_icon(result.success)));
+        }
+
+        for item in &result.items {
+            group.add(&item_row(item));
+        }
+
+        // Show why the plugin failed, unless the failed items already do
+        match &result.failure {
+            Some(PluginError::PartialFailure { .. }) | None => {
+                if result.items.is_empty() {
+                    group.add(&ActionRow::builder().title("No changes").build());
+                }
+            }
+            Some(failure) => group.add(&error_row(
+                &failure.to_string(),
+                result.error.as_deref().unwrap_or_default(),
+            )),
+        }
+
+        page.add(&group);
+    }
+
+    page
+}
+
+/// Describe how the update went, i.e. "Succeeded in 1m 23s"
+fn outcome(result: &PluginResult) -> String {
+    let elapsed = utils::format_duration(result.elapsed);
+    if result.cancelled && result.elapsed.is_zero() {
+        "Cancelled before starting".to_string()
+    } else if result.cancelled {
+        format!("Cancelled after {}", elapsed)
+    } else if result.success {
+        format!("Succeeded in {}", elapsed)
+    } else {
+        format!("Failed in {}", elapsed)
+    }
+}
+
+fn status_icon(success: bool) -> gtk::Image {
+    if success {
+        gtk::Image::from_icon_name("emblem-ok-symbolic")
+    } else {
+        gtk::Image::from_icon_name("dialog-error-symbolic")
+    }
+}
+
+/// A row describing an item's outcome, i.e. "k9s: 0.50.15 → 0.50.16"
+fn item_row(item: &ItemResult) -> gtk::Widget {
+    if let Some(error) = item.error.as_ref().filter(|_| !item.success) {
+        return error_row(&item.name, error).upcast();
+    }
+
+    let subtitle = match (&item.old_version, &item.new_version) {
+        (Some(old), Some(new)) => format!("{} → {}", old, new),
+        (None, Some(new)) => new.clone(),
+        _ => if item.success { "Updated" } else { "Failed" }.to_string(),
+    };
+
+    let row = ActionRow::builder()
+        .title(&item.name)
+        .subtitle(subtitle)
+        .use_markup(false)
+        .build();
+    row.add_prefix(&status_icon(item.success));
+    row.upcast()
+}
+
+/// An expandable row showing the error output of a failure
+fn error_row(title: &str, error: &str) -> gtk::Widget {
+    let row = ExpanderRow::builder()
+        .title(title)
+        .subtitle(error.lines().find(|l| !l.trim().is_empty()).unwrap_or(""))
+        .use_markup(false)
+        .build();
+    row.add_prefix(&status_icon(false));
+
+    let label = gtk::Label::builder()
+        .label(error.trim())
+        .selectable(true)
+        .wrap(true)
+        .xalign(0.0)
+        .css_classes(["monospace"])
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    row.add_row(&label);
+
+    row.upcast()
+}
@@ -0,0 +1,250 @@
+// A modal to go back to the OS that was installed before the last update, i.e. if it broke something.
+use crate::{history, utils};
+
+use adw::prelude::*;
+use adw::{ActionRow, PreferencesGroup, PreferencesPage};
+use renovatio::engine::EngineEvent;
+use renovatio::{Deployment, Outcome, PluginEvent, PluginMetadata, RollbackTarget};
+
+use std::thread;
+
+pub fn show(parent: &impl IsA<gtk::Widget>, plugins: &[PluginMetadata]) {
+    // Only the enabled plugins manage the system
+    let enabled = utils::enabled_plugins();
+    let plugins: Vec<&PluginMetadata> = plugins
+        .iter()
+        .filter(|metadata| metadata.capabilities.rollback)
+        .filter(|metadata| metadata.availability.is_available())
+        .filter(|metadata| enabled.contains(&metadata.name))
+        .collect();
+
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+
+    if plugins.is_empty() {
+        let status = adw::StatusPage::builder()
+            .icon_name("edit-undo-symbolic")
+            .title("Nothing to Roll Back")
+            .description(
+                "Enable a plugin that can roll back, like bootc or rpm-ostree, in the preferences",
+            )
+            .build();
+        toolbar_view.set_content(Some(&status));
+    } else {
+        let page = PreferencesPage::new();
+        for metadata in plugins {
+            page.add(&plugin_group(&metadata.name));
+        }
+        toolbar_view.set_content(Some(&page));
+    }
+
+    let dialog = adw::Dialog::builder()
+        .title("Roll Back")
+        .content_width(450)
+        .content_height(400)
+        .child(&toolbar_view)
+        .build();
+
+    dialog.present(Some(parent));
+}
+
+/// A group comparing what's running with what `plugin` would roll back to, with a button
+/// to roll back
+fn plugin_group(plugin: &str) -> PreferencesGroup {
+    let group = PreferencesGroup::builder()
+        .title(glib::markup_escape_text(plugin))
+        .build();
+
+    let loading = ActionRow::builder()
+        .title("Looking for the previous version...")
+        .build();
+    loading.add_suffix(&adw::Spinner::new());
+    group.add(&loading);
+
+    // Asking the plugin can take a while, i.e. while it authenticates
+    let (tx, rx) = flume::bounded::<Option<RollbackTarget>>(1);
+    let name = plugin.to_string();
+    thread::spawn(move || {
+        let (events, _rx) = flume::unbounded::<EngineEvent>();
+        let engine = utils::load_engine(&utils::plugin_paths(std::slice::from_ref(&name)), events);
+        let _ = tx.send(engine.rollback_target(&name));
+    });
+
+    let plugin = plugin.to_string();
+    glib::spawn_future_local(glib::clone!(
+        #[weak]
+        group,
+        async move {
+            let Ok(target) = rx.recv_async().await else {
+                return;
+            };
+            group.remove(&loading);
+
+            let Some(target) = target else {
+                group.set_description(Some("There's no previous version to go back to"));
+                return;
+            };
+
+            group.add(&deployment_row("Running Now", &target.booted));
+            if target.queued {
+                group.add(&deployment_row("Boots Next", &target.rollback));
+                group.set_description(Some("The previous version boots the next time you restart"));
+                group.set_header_suffix(Some(&restart_button()));
+            } else {
+                group.add(&deployment_row("Previous Version", &target.rollback));
+                group.set_header_suffix(Some(&rollback_button(&plugin, &group, &target)));
+            }
+        }
+    ));
+
+    group
+}
+
+/// A row showing the version and digest of a deployment
+fn deployment_row(title: &str, deployment: &Deployment) -> ActionRow {
+    let version = deployment
+        .version
+        .clone()
+        .unwrap_or_else(|| "Unknown version".to_string());
+    let subtitle = match &deployment.digest {
+        Some(digest) => format!("{}\n{}", version, digest),
+        None => version,
+    };
+
+    let row = ActionRow::builder()
+        .title(title)
+        .subtitle(subtitle)
+        .subtitle_selectable(true)
+        .use_markup(false)
+        .build();
+    if deployment.pinned {
+        let pinned = gtk::Image::from_icon_name("view-pin-symbolic");
+        pinned.set_tooltip_text(Some("Pinned"));
+        row.add_suffix(&pinned);
+    }
+    row
+}
+
+/// A button that rolls `plugin` back to `target`, once the user confirms it
+fn rollback_button(plugin: &str, group: &PreferencesGroup, target: &RollbackTarget) -> gtk::Button {
+    let button = gtk::Button::builder()
+        .label("Roll Back")
+        .valign(gtk::Align::Center)
+        .css_classes(["destructive-action"])
+        .build();
+
+    let plugin = plugin.to_string();
+    let version = target
+        .rollback
+        .version
+        .clone()
+        .unwrap_or_else(|| "the Previous Version".to_string());
+    button.connect_clicked(glib::clone!(
+        #[weak]
+        group,
+        move |button| {
+            let confirm = adw::AlertDialog::builder()
+                .heading(format!("Roll Back to {}?", version))
+                .body(
+                    "The previous version boots the next time you restart. You can update \
+                     again afterwards.",
+                )
+                .build();
+            confirm.add_responses(&[("cancel", "Cancel"), ("rollback", "Roll Back")]);
+            confirm.set_response_appearance("rollback", adw::ResponseAppearance::Destructive);
+            confirm.connect_response(
+                Some("rollback"),
+                glib::clone!(
+                    #[weak]
+                    group,
+                    #[weak]
+                    button,
+                    #[strong]
+                    plugin,
+                    move |_, _| rollback(&plugin, &group, &button)
+                ),
+            );
+            confirm.present(Some(button));
+        }
+    ));
+
+    button
+}
+
+/// Roll `plugin` back, following along in its `group`
+fn rollback(plugin: &str, group: &PreferencesGroup, button: &gtk::Button) {
+    button.set_sensitive(false);
+
+    let status = ActionRow::builder()
+        .title("Rolling back...")
+        .use_markup(false)
+        .build();
+    status.add_suffix(&adw::Spinner::new());
+    group.add(&status);
+
+    let (tx, rx) = flume::unbounded::<EngineEvent>();
+    let tx = history::recorder(tx, false);
+    let name = plugin.to_string();
+    thread::spawn(move || {
+        let mut engine = utils::load_engine(&utils::plugin_paths(std::slice::from_ref(&name)), tx);
+        engine.rollback(&name);
+    });
+
+    glib::spawn_future_local(glib::clone!(
+        #[weak]
+        group,
+        #[weak]
+        button,
+        async move {
+            let mut finished = false;
+            while let Ok(event) = rx.recv_async().await {
+                match event {
+                    EngineEvent::Plugin {
+                        event: PluginEvent::Status { message },
+                        ..
+                    } => status.set_title(&message),
+                    EngineEvent::PluginFinished(result) => {
+                        finished = true;
+                        group.remove(&status);
+
+                        if let Outcome::Failed { error } = result.outcome() {
+                            group.set_description(Some(&format!(
+                                "Failed to roll back: {}",
+                                glib::markup_escape_text(
+                                    &error.unwrap_or_else(|| "unknown error".to_string())
+                                )
+                            )));
+                            button.set_sensitive(true);
+                        } else if result.reboot_required {
+                            group.set_description(Some("Restart to finish rolling back"));
+                            group.set_header_suffix(Some(&restart_button()));
+                        } else {
+                            group.set_description(Some("Rolled back"));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // The plugin couldn't be loaded, so it never started
+            if !finished {
+                group.remove(&status);
+                group.set_description(Some("Failed to load the plugin"));
+                button.set_sensitive(true);
+            }
+        }
+    ));
+}
+
+/// A button that restarts the computer, to boot the rolled back version
+fn restart_button() -> gtk::Button {
+    let button = gtk::Button::builder()
+        .label("Restart Now")
+        .valign(gtk::Align::Center)
+        .css_classes(["suggested-action"])
+        .build();
+    button.connect_clicked(|_| {
+        utils::reboot_system();
+    });
+    button
+}
@@ -0,0 +1,208 @@
+//! Deciding the order plugins run in.
+//!
+//! Plugins declare a `RunOrder`, i.e. rpm-ostree runs before flatpak so new runtimes land on
+//! the new OS, and the user can arrange the plugins in the preferences. Where the two
+//! disagree, the user's arrangement wins.
+
+use crate::RunOrder;
+
+/// Sort plugins so each runs after the plugins it depends on, returning their indices in the
+/// order they should run.
+///
+/// `plugins` holds the name and declared run order of each plugin. `user_order` holds the
+/// names of the plugins in the order the user arranged them, if they did. Otherwise plugins
+/// keep their position in `user_order`, then in `plugins`. Constraints that form a cycle are
+/// broken in that same order.
+pub fn sort(plugins: &[(&str, &RunOrder)], user_order: &[String]) -> Vec<usize> {
+    let count = plugins.len();
+    let position = |name: &str| user_order.iter().position(|n| n == name);
+
+    // The order to fall back on, where no constraint applies
+    let rank: Vec<usize> = plugins
+        .iter()
+        .enumerate()
+        .map(|(index, (name, _))| position(name).unwrap_or(user_order.len() + index))
+        .collect();
+
+    // runs_before[a][b] is set if plugin a has to run before plugin b
+    let mut runs_before = vec![vec![false; count]; count];
+    for a in 0..count {
+        for b in 0..count {
+            if a == b {
+                continue;
+            }
+
+            runs_before[a][b] = match (position(plugins[a].0), position(plugins[b].0)) {
+                // The user placed both plugins, so their arrangement wins
+                (Some(a_position), Some(b_position)) => a_position < b_position,
+                _ => declares_before(plugins[a], plugins[b]),
+            };
+        }
+    }
+
+    // Kahn's algorithm, picking the highest ranked plugin whenever there's a choice
+    let mut sorted = Vec::with_capacity(count);
+    let mut remaining: Vec<usize> = (0..count).collect();
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .copied()
+            .filter(|&b| !remaining.iter().any(|&a| runs_before[a][b]))
+            .min_by_key(|&index| rank[index]);
+
+        // Everything left is waiting on something else, so break the cycle by rank
+        let next = ready.unwrap_or_else(|| {
+            remaining
+                .iter()
+                .copied()
+                .min_by_key(|&index| rank[index])
+                .unwrap_or_default()
+        });
+
+        remaining.retain(|&index| index != next);
+        sorted.push(next);
+    }
+
+    sorted
+}
+
+/// For each plugin, the indices of the plugins it has to wait for when plugins update in
+/// parallel: the ones it's declared to run after, unless the user placed them the other way
+/// around. Unlike `sort`, the user's arrangement alone doesn't make plugins wait on each other.
+pub fn dependencies(plugins: &[(&str, &RunOrder)], user_order: &[String]) -> Vec<Vec<usize>> {
+    let position = |name: &str| user_order.iter().position(|n| n == name);
+
+    (0..plugins.len())
+        .map(|b| {
+            (0..plugins.len())
+                .filter(|&a| a != b && declares_before(plugins[a], plugins[b]))
+                .filter(
+                    |&a| match (position(plugins[a].0), position(plugins[b].0)) {
+                        (Some(a_position), Some(b_position)) => a_position < b_position,
+                        _ => true,
+                    },
+                )
+                .collect()
+        })
+        .collect()
+}
+
+/// Determine if the plugins declare that `a` runs before `b`.
+///
+/// Naming a plugin takes precedence over `"*"`, and contradicting declarations cancel out.
+fn declares_before(a: (&str, &RunOrder), b: (&str, &RunOrder)) -> bool {
+    if names_before(a, b) || names_before(b, a) {
+        names_before(a, b) && !names_before(b, a)
+    } else {
+        wildcard_before(a, b) && !wildcard_before(b, a)
+    }
+}
+
+/// Determine if either plugin names the other to say that `a` runs before `b`
+fn names_before(
+    (a_name, a_order): (&str, &RunOrder),
+    (b_name, b_order): (&str, &RunOrder),
+) -> bool {
+    a_order.before.iter().any(|name| name == b_name)
+        || b_order.after.iter().any(|name| name == a_name)
+}
+
+/// Determine if either plugin uses `"*"` to say that `a` runs before `b`. A `"*"` only applies
+/// to plugins that don't use it too, so two plugins that both run last don't wait on each other.
+fn wildcard_before((_, a_order): (&str, &RunOrder), (_, b_order): (&str, &RunOrder)) -> bool {
+    let all = |names: &[String]| names.iter().any(|name| name == "*");
+
+    (all(&a_order.before) && !all(&b_order.before)) || (all(&b_order.after) && !all(&a_order.after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sort the named plugins, returning their names in the order they run
+    fn sorted(plugins: &[(&str, RunOrder)], user_order: &[&str]) -> Vec<String> {
+        let plugins: Vec<(&str, &RunOrder)> = plugins
+            .iter()
+            .map(|(name, run_order)| (*name, run_order))
+            .collect();
+        let user_order: Vec<String> = user_order.iter().map(|name| name.to_string()).collect();
+
+        sort(&plugins, &user_order)
+            .into_iter()
+            .map(|index| plugins[index].0.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_given_order_without_constraints() {
+        let plugins = [("a", RunOrder::default()), ("b", RunOrder::default())];
+        assert_eq!(sorted(&plugins, &[]), ["a", "b"]);
+    }
+
+    #[test]
+    fn naming_a_plugin_beats_a_wildcard() {
+        let plugins = [
+            ("flatpak", RunOrder::default()),
+            ("rpm-ostree", RunOrder::before(&["*"])),
+            ("uupd", RunOrder::before(&["rpm-ostree"])),
+            ("brew", RunOrder::after(&["flatpak"])),
+        ];
+        assert_eq!(
+            sorted(&plugins, &[]),
+            ["uupd", "rpm-ostree", "flatpak", "brew"]
+        );
+    }
+
+    #[test]
+    fn plugins_that_all_run_last_keep_their_order() {
+        let plugins = [
+            ("a", RunOrder::after(&["*"])),
+            ("b", RunOrder::after(&["*"])),
+            ("c", RunOrder::default()),
+        ];
+        assert_eq!(sorted(&plugins, &[]), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn cycles_are_broken_in_the_saved_order() {
+        let plugins = [
+            ("a", RunOrder::before(&["b"])),
+            ("b", RunOrder::before(&["c"])),
+            ("c", RunOrder::before(&["a"])),
+        ];
+        assert_eq!(sorted(&plugins, &[]), ["a", "b", "c"]);
+        assert_eq!(sorted(&plugins, &["b"]), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn the_saved_order_beats_the_declared_order() {
+        let plugins = [("a", RunOrder::before(&["b"])), ("b", RunOrder::default())];
+        assert_eq!(sorted(&plugins, &["b", "a"]), ["b", "a"]);
+
+        // Only plugins the user placed both of are overridden
+        assert_eq!(sorted(&plugins, &["b"]), ["a", "b"]);
+    }
+
+    #[test]
+    fn unknown_names_in_the_saved_order_are_ignored() {
+        let plugins = [("a", RunOrder::default()), ("b", RunOrder::default())];
+        assert_eq!(sorted(&plugins, &["uninstalled", "b", "a"]), ["b", "a"]);
+    }
+
+    #[test]
+    fn plugins_wait_for_what_they_run_after() {
+        let a = RunOrder::before(&["*"]);
+        let b = RunOrder::default();
+        let c = RunOrder::after(&["b"]);
+        let plugins = [("a", &a), ("b", &b), ("c", &c)];
+
+        assert_eq!(dependencies(&plugins, &[]), [vec![], vec![0], vec![0, 1]]);
+
+        // Reversing two plugins in the preferences means they don't wait for each other
+        let user_order = ["c".to_string(), "b".to_string()];
+        assert_eq!(
+            dependencies(&plugins, &user_order),
+            [vec![], vec![0], vec![0]]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use crate::utils;
+
+use adw::prelude::*;
+use adw::{ActionRow, PreferencesGroup};
+use gtk::{Image, ProgressBar};
+use renovatio::{Outcome, PluginEvent, PluginMetadata};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// ProgressView lists each plugin in the run with its status, progress and how it finished
+#[derive(Clone)]
+pub struct ProgressView {
+    pub container: PreferencesGroup,
+    rows: Rc<RefCell<HashMap<String, PluginProgressRow>>>,
+}
+
+/// The progress of a single plugin
+struct PluginProgressRow {
+    row: ActionRow,
+    bar: ProgressBar,
+    spinner: adw::Spinner,
+    state: Image,
+
+    /// When the plugin started updating, if it has
+    started: Cell<Option<Instant>>,
+}
+
+impl Default for ProgressView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressView {
+    pub fn new() -> Self {
+        let container = PreferencesGroup::builder()
+            .margin_top(12)
+            .margin_start(12)
+            .margin_end(12)
+            .visible(false)
+            .build();
+
+        Self {
+            container,
+            rows: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Replace the rows with one for each of `plugins`, in the order given, waiting to start
+    pub fn set_plugins(&self, plugins: &[PluginMetadata]) {
+        self.clear();
+        for metadata in plugins {
+            self.row(&metadata.name, &metadata.icon_name);
+        }
+    }
+
+    /// Show what a plugin reported
+    pub fn handle(&self, plugin: &str, event: &PluginEvent) {
+        // Plugins outside the list are added as they start
+        if matches!(event, PluginEvent::Started) {
+            self.row(plugin, "application-x-addon-symbolic");
+        }
+
+        let rows = self.rows.borrow();
+        let Some(row) = rows.get(plugin) else {
+            return;
+        };
+
+        match event {
+            PluginEvent::Started => row.start(),
+            PluginEvent::Status { message } => row.row.set_subtitle(message),
+            PluginEvent::StepStarted { item } => {
+                row.row.set_subtitle(&format!("Updating {}...", item.name))
+            }
+            PluginEvent::Progress {
+                fraction: Some(fraction),
+            } => {
+                row.bar.set_pulse_step(0.0);
+                row.bar.set_fraction(*fraction);
+            }
+            PluginEvent::Progress { fraction: None } => {
+                row.bar.set_pulse_step(0.25);
+                row.bar.pulse();
+            }
+            PluginEvent::RebootRequired { reason } => row.row.set_subtitle(reason),
+            PluginEvent::Finished { outcome } => row.finish(outcome),
+            PluginEvent::StepFinished { .. } | PluginEvent::Log { .. } => {}
+        }
+    }
+
+    /// Pulse the progress bars of plugins that can't report incremental progress
+    pub fn pulse(&self) {
+        for row in self.rows.borrow().values() {
+            if row.bar.pulse_step() > 0.0 {
+                row.bar.pulse();
+            }
+        }
+    }
+
+    /// Remove the rows of a previous run
+    pub fn clear(&self) {
+        for (_, row) in self.rows.borrow_mut().drain() {
+            self.container.remove(&row.row);
+        }
+        self.container.set_visible(false);
+    }
+
+    /// Add a row for `plugin`, unless it already has one
+    fn row(&self, plugin: &str, icon_name: &str) {
+        let mut rows = self.rows.borrow_mut();
+        if rows.contains_key(plugin) {
+            return;
+        }
+
+        let row = PluginProgressRow::new(plugin, icon_name);
+        self.container.add(&row.row);
+        self.container.set_visible(true);
+        rows.insert(plugin.to_string(), row);
+    }
+}
+
+impl PluginProgressRow {
+    fn new(plugin: &str, icon_name: &str) -> Self {
+        let row = ActionRow::builder()
+            .title(plugin)
+            .subtitle("Waiting")
+            .use_markup(false)
+            .build();
+        row.add_prefix(&Image::from_icon_name(icon_name));
+
+        let bar = ProgressBar::builder()
+            .pulse_step(0.0)
+            .valign(gtk::Align::Center)
+            .width_request(100)
+            .visible(false)
+            .build();
+        row.add_suffix(&bar);
+
+        let spinner = adw::Spinner::builder().visible(false).build();
+        row.add_suffix(&spinner);
+
+        let state = Image::builder().visible(false).build();
+        row.add_suffix(&state);
+
+        Self {
+            row,
+            bar,
+            spinner,
+            state,
+            started: Cell::new(None),
+        }
+    }
+
+    /// Show that the plugin started updating
+    fn start(&self) {
+        self.started.set(Some(Instant::now()));
+        self.row.set_subtitle("Starting...");
+        self.bar.set_fraction(0.0);
+        self.bar.set_visible(true);
+        self.spinner.set_visible(true);
+    }
+
+    /// Show how the plugin's update ended, i.e. "Succeeded in 1m 23s"
+    fn finish(&self, outcome: &Outcome) {
+        let elapsed = self
+            .started
+            .get()
+            .map(|started| utils::format_duration(started.elapsed()));
+
+        let (subtitle, icon) = match (outcome, elapsed) {
+            (Outcome::Cancelled, None) => (
+                "Cancelled before starting".to_string(),
+                "process-stop-symbolic",
+            ),
+            (Outcome::Cancelled, Some(elapsed)) => (
+                format!("Cancelled after {}", elapsed),
+                "process-stop-symbolic",
+            ),
+            (Outcome::Succeeded, elapsed) => (
+                format!("Succeeded in {}", elapsed.unwrap_or_default()),
+                "emblem-ok-symbolic",
+            ),
+            (Outcome::Failed { .. }, elapsed) => (
+                format!("Failed in {}", elapsed.unwrap_or_default()),
+                "dialog-error-symbolic",
+            ),
+        };
+
+        self.row.set_subtitle(&subtitle);
+        self.bar.set_pulse_step(0.0);
+        self.bar.set_visible(false);
+        self.spinner.set_visible(false);
+        self.state.set_icon_name(Some(icon));
+        self.state.set_visible(true);
+    }
+}
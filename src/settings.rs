@@ -0,0 +1,222 @@
+//! Plugin settings.
+//!
+//! A plugin declares the `Setting`s it understands, i.e. whether brew upgrades casks, and the
+//! preferences show them on the plugin's page. The values are saved to
+//! `$XDG_CONFIG_HOME/renovatio/plugins/<plugin>.json` and passed to `Plugin::update`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Setting describes one of a plugin's settings
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Setting {
+    /// The key the value is saved under
+    pub key: String,
+
+    pub title: String,
+
+    #[serde(default)]
+    pub description: String,
+
+    #[serde(flatten)]
+    pub kind: SettingKind,
+}
+
+/// SettingKind is the type of value a setting holds, and its default
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SettingKind {
+    /// On or off
+    Bool { default: bool },
+
+    /// One of `choices`
+    Choice {
+        choices: Vec<String>,
+        default: String,
+    },
+
+    /// Any number of strings, i.e. names to skip
+    List {
+        #[serde(default)]
+        default: Vec<String>,
+    },
+}
+
+impl Setting {
+    /// A setting that's on or off
+    pub fn bool(key: &str, title: &str, description: &str, default: bool) -> Self {
+        Self::new(key, title, description, SettingKind::Bool { default })
+    }
+
+    /// A setting that's one of `choices`
+    pub fn choice(
+        key: &str,
+        title: &str,
+        description: &str,
+        choices: &[&str],
+        default: &str,
+    ) -> Self {
+        Self::new(
+            key,
+            title,
+            description,
+            SettingKind::Choice {
+                choices: choices.iter().map(|choice| choice.to_string()).collect(),
+                default: default.to_string(),
+            },
+        )
+    }
+
+    /// A setting that holds a list of strings
+    pub fn list(key: &str, title: &str, description: &str, default: &[&str]) -> Self {
+        Self::new(
+            key,
+            title,
+            description,
+            SettingKind::List {
+                default: default.iter().map(|item| item.to_string()).collect(),
+            },
+        )
+    }
+
+    fn new(key: &str, title: &str, description: &str, kind: SettingKind) -> Self {
+        Self {
+            key: key.to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            kind,
+        }
+    }
+
+    /// The value of the setting when the user hasn't changed it
+    pub fn default_value(&self) -> SettingValue {
+        match &self.kind {
+            SettingKind::Bool { default } => SettingValue::Bool(*default),
+            SettingKind::Choice { default, .. } => SettingValue::String(default.clone()),
+            SettingKind::List { default } => SettingValue::List(default.clone()),
+        }
+    }
+
+    /// Determine if `value` can be saved for this setting
+    pub fn accepts(&self, value: &SettingValue) -> bool {
+        match (&self.kind, value) {
+            (SettingKind::Bool { .. }, SettingValue::Bool(_)) => true,
+            (SettingKind::Choice { choices, .. }, SettingValue::String(choice)) => {
+                choices.contains(choice)
+            }
+            (SettingKind::List { .. }, SettingValue::List(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// SettingValue is the value of a setting
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SettingValue {
+    Bool(bool),
+    String(String),
+    List(Vec<String>),
+}
+
+/// PluginSettings holds the value of each of a plugin's settings
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PluginSettings {
+    values: BTreeMap<String, SettingValue>,
+}
+
+impl PluginSettings {
+    /// The default value of each setting in `schema`
+    pub fn defaults(schema: &[Setting]) -> Self {
+        Self {
+            values: schema
+                .iter()
+                .map(|setting| (setting.key.clone(), setting.default_value()))
+                .collect(),
+        }
+    }
+
+    /// Load the saved settings of `plugin`, using the default for each setting in `schema`
+    /// that wasn't saved or no longer fits it
+    pub fn load(plugin: &str, schema: &[Setting]) -> Self {
+        let mut settings = Self::defaults(schema);
+
+        // Read each value on its own, so one that's invalid doesn't lose the others
+        let saved = std::fs::read_to_string(Self::path(plugin))
+            .ok()
+            .and_then(|json| serde_json::from_str::<BTreeMap<String, Value>>(&json).ok())
+            .unwrap_or_default();
+
+        for setting in schema {
+            if let Some(value) = saved.get(&setting.key)
+                && let Ok(value) = SettingValue::deserialize(value)
+                && setting.accepts(&value)
+            {
+                settings.set(&setting.key, value);
+            }
+        }
+
+        settings
+    }
+
+    /// Save the settings of `plugin`
+    pub fn save(&self, plugin: &str) -> std::io::Result<()> {
+        let path = Self::path(plugin);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+    }
+
+    /// The file the settings of `plugin` are saved to
+    pub fn path(plugin: &str) -> PathBuf {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config")
+            });
+
+        config_dir
+            .join("renovatio")
+            .join("plugins")
+            .join(format!("{}.json", plugin))
+    }
+
+    /// Get the value of the setting `key`
+    pub fn get(&self, key: &str) -> Option<&SettingValue> {
+        self.values.get(key)
+    }
+
+    /// Set the value of the setting `key`
+    pub fn set(&mut self, key: &str, value: SettingValue) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    /// Get the value of the bool setting `key`, or false if it isn't one
+    pub fn bool(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(SettingValue::Bool(true)))
+    }
+
+    /// Get the value of the choice setting `key`, or "" if it isn't one
+    pub fn choice(&self, key: &str) -> &str {
+        match self.get(key) {
+            Some(SettingValue::String(choice)) => choice,
+            _ => "",
+        }
+    }
+
+    /// Get the value of the list setting `key`, or an empty list if it isn't one
+    pub fn list(&self, key: &str) -> &[String] {
+        match self.get(key) {
+            Some(SettingValue::List(items)) => items,
+            _ => &[],
+        }
+    }
+}
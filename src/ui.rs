@@ -1,7 +1,132 @@
-use super::config;
+use super::{config, modals};
 
 use adw::prelude::*;
-use gtk::{Box, Button, CheckButton, ProgressBar};
+use gtk::{Box, Button, CheckButton, Label, ListBox, ProgressBar, ScrolledWindow};
+use renovatio::{PendingUpdate, Selection};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The checkbox of each pending update, by plugin name
+type PendingItems = HashMap<String, Vec<(String, CheckButton)>>;
+
+/// PendingList shows the pending updates of each plugin, with a checkbox per update
+#[derive(Clone)]
+pub struct PendingList {
+    pub list: ListBox,
+    items: Rc<RefCell<PendingItems>>,
+}
+
+impl Default for PendingList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PendingList {
+    pub fn new() -> Self {
+        let list = ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .visible(false)
+            .build();
+
+        Self {
+            list,
+            items: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Add a row for `plugin`, with a checkbox for each of its pending updates, and a button
+    /// showing each update's release notes if the plugin has a `changelog`
+    pub fn add_plugin(&self, plugin: &str, updates: &[PendingUpdate], changelog: bool) {
+        if updates.is_empty() {
+            return;
+        }
+
+        let expander = adw::ExpanderRow::builder()
+            .title(plugin)
+            .subtitle(format!("{} pending", updates.len()))
+            .use_markup(false)
+            .build();
+
+        let mut items = Vec::new();
+        for update in updates {
+            let check_button = CheckButton::builder().active(true).build();
+
+            let row = adw::ActionRow::builder()
+                .title(&update.name)
+                .subtitle(pending_update_subtitle(update))
+                .use_markup(false)
+                .activatable_widget(&check_button)
+                .build();
+            row.add_prefix(&check_button);
+            if changelog {
+                row.add_suffix(&changelog_button(plugin, update));
+            }
+            expander.add_row(&row);
+
+            items.push((update.name.clone(), check_button));
+        }
+
+        self.items.borrow_mut().insert(plugin.to_string(), items);
+        self.list.append(&expander);
+        self.list.set_visible(true);
+    }
+
+    /// Get the selected updates of each plugin. Plugins that didn't report
+    /// any pending updates aren't included, and should update everything.
+    pub fn selections(&self) -> HashMap<String, Selection> {
+        self.items
+            .borrow()
+            .iter()
+            .map(|(plugin, items)| {
+                let selected = items
+                    .iter()
+                    .filter(|(_, check_button)| check_button.is_active())
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                (plugin.clone(), Selection::Only(selected))
+            })
+            .collect()
+    }
+
+    /// Prevent the selection from changing while updates are running
+    pub fn set_sensitive(&self, sensitive: bool) {
+        self.list.set_sensitive(sensitive);
+    }
+}
+
+/// A button showing what's new in `plugin`'s `update`
+fn changelog_button(plugin: &str, update: &PendingUpdate) -> Button {
+    let button = Button::builder()
+        .icon_name("text-x-generic-symbolic")
+        .tooltip_text("What's New")
+        .valign(gtk::Align::Center)
+        .css_classes(["flat"])
+        .build();
+
+    let (plugin, update) = (plugin.to_string(), update.clone());
+    button.connect_clicked(move |button| {
+        modals::changelog::show(button, &plugin, &update);
+    });
+    button
+}
+
+/// Describe a pending update, i.e. "1.0 → 1.1 (12.3 MB)"
+fn pending_update_subtitle(update: &PendingUpdate) -> String {
+    let mut subtitle = match (&update.current_version, &update.new_version) {
+        (Some(current), Some(new)) => format!("{} → {}", current, new),
+        (None, Some(new)) => new.clone(),
+        _ => update.kind.clone().unwrap_or_default(),
+    };
+
+    if let Some(size) = &update.size {
+        subtitle = format!("{} ({})", subtitle, size);
+    }
+    subtitle
+}
 
 pub fn get_apply_check_button() -> CheckButton {
     let settings = gio::Settings::new(config::APP_ID);
@@ -25,17 +150,52 @@ pub fn get_apply_check_button() -> CheckButton {
     cb
 }
 
-pub fn get_plugin_progress_bar() -> ProgressBar {
-    ProgressBar::builder()
+/// A banner saying what happened while updating unattended, hidden until there's a run to show
+pub fn get_unattended_banner() -> adw::Banner {
+    adw::Banner::builder()
+        .button_label("Show Details")
+        .revealed(false)
+        .build()
+}
+
+pub fn get_pending_label() -> Label {
+    Label::builder()
+        .label("Checking for updates...")
         .margin_top(12)
-        .margin_bottom(3)
         .margin_start(12)
         .margin_end(12)
-        .pulse_step(0.0)
-        // .pulse_step(25.0)
-        .show_text(true)
-        .text("")
-        .visible(false)
+        .wrap(true)
+        .build()
+}
+
+/// Summarize the pending updates of each plugin, i.e. "12 flatpak, 3 brew, bootc 42.20261010"
+pub fn pending_summary(pending: &[(String, Vec<PendingUpdate>)]) -> String {
+    let parts = pending
+        .iter()
+        .filter(|(_, updates)| !updates.is_empty())
+        .map(|(name, updates)| match &updates[..] {
+            [update] if update.new_version.is_some() => {
+                format!("{} {}", name, update.new_version.as_deref().unwrap_or(""))
+            }
+            _ => format!("{} {}", updates.len(), name),
+        })
+        .collect::<Vec<String>>();
+
+    if parts.is_empty() {
+        "Checking for updates...".to_string()
+    } else {
+        format!("Pending updates: {}", parts.join(", "))
+    }
+}
+
+pub fn get_pending_scrolled_window(pending_list: &PendingList) -> ScrolledWindow {
+    ScrolledWindow::builder()
+        .child(&pending_list.list)
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .propagate_natural_height(true)
+        .max_content_height(300)
+        .margin_start(12)
+        .margin_end(12)
         .build()
 }
 
@@ -61,12 +221,25 @@ pub fn get_update_button() -> Button {
         .build()
 }
 
+pub fn get_cancel_button() -> Button {
+    Button::builder()
+        .label("Cancel")
+        .css_classes(["destructive-action"])
+        .visible(false)
+        .margin_bottom(6)
+        .margin_start(12)
+        .margin_end(12)
+        .build()
+}
+
 pub fn get_header_bar() -> adw::HeaderBar {
     let header_bar = adw::HeaderBar::new();
     let window_title = adw::WindowTitle::builder().title("Renovatio").build();
 
     let main_menu = gio::Menu::new();
     main_menu.append(Some("About"), Some("app.about"));
+    main_menu.append(Some("History"), Some("app.history"));
+    main_menu.append(Some("Roll Back…"), Some("app.rollback"));
     main_menu.append(Some("Preferences"), Some("app.preferences"));
     main_menu.append(Some("Quit"), Some("app.quit"));
 
@@ -79,13 +252,8 @@ pub fn get_header_bar() -> adw::HeaderBar {
     header_bar
 }
 
-pub fn get_main_container(
-    header_bar: &adw::HeaderBar,
-    update_button: &Button,
-    apply_check_button: &CheckButton,
-    plugin_progress_bar: &ProgressBar,
-    total_progress_bar: &ProgressBar,
-) -> Box {
+/// Create the main container, with the header bar above the `children`, top to bottom
+pub fn get_main_container(header_bar: &adw::HeaderBar, children: &[&gtk::Widget]) -> Box {
     // Create main container
     let parent = Box::new(gtk::Orientation::Vertical, 6);
 
@@ -93,10 +261,9 @@ pub fn get_main_container(
 
     let main_box = Box::new(gtk::Orientation::Vertical, 6);
 
-    main_box.append(update_button);
-    main_box.append(apply_check_button);
-    main_box.append(plugin_progress_bar);
-    main_box.append(total_progress_bar);
+    for child in children {
+        main_box.append(*child);
+    }
 
     let clamp = adw::Clamp::builder()
         .child(&main_box)
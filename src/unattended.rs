@@ -0,0 +1,303 @@
+//! Unattended updates.
+//!
+//! With unattended updates turned on, the background service applies the pending updates of
+//! the enabled plugins during the maintenance window in the preferences, i.e. Sundays from
+//! 02:00 to 04:00, as long as the computer is on AC power and the network isn't metered. The
+//! window shows what happened, from the history, the next time it opens.
+//!
+//! Nobody is around to type a password, so plugins that need an administrator's privileges
+//! are left out. That includes the OS plugins, so an unattended run never needs a reboot.
+
+use crate::history::{self, Run};
+use crate::{config, utils};
+
+use adw::prelude::*;
+use renovatio::engine::{EngineEvent, EngineState};
+use renovatio::{CancellationToken, Privileges};
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+/// How often to see if it's time to update
+const POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The id of the notification
+const NOTIFICATION_ID: &str = "unattended-updates";
+
+/// The names of the days of the week, as numbered by the `maintenance-day` key
+pub const DAYS: &[&str] = &[
+    "Every Day",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// Apply the pending updates whenever the maintenance window comes around
+pub fn start(app: &adw::Application) {
+    let running = Rc::new(Cell::new(false));
+
+    // Why the last poll didn't update, to only say so when it changes
+    let waiting = Rc::new(Cell::new(None));
+
+    glib::timeout_add_local(
+        POLL_INTERVAL,
+        glib::clone!(
+            #[weak]
+            app,
+            #[upgrade_or]
+            glib::ControlFlow::Break,
+            move || {
+                if running.get() {
+                    return glib::ControlFlow::Continue;
+                }
+
+                let reason = blocked(&app);
+                if reason != waiting.replace(reason)
+                    && let Some(reason) = reason
+                {
+                    println!("Not updating unattended: {}", reason);
+                }
+                if reason.is_none() {
+                    run(&app, running.clone());
+                }
+                glib::ControlFlow::Continue
+            }
+        ),
+    );
+}
+
+/// Why the updates can't run unattended right now, if they can't
+fn blocked(app: &adw::Application) -> Option<&'static str> {
+    let settings = gio::Settings::new(config::APP_ID);
+    if !settings.boolean("unattended-updates") {
+        return Some("turned off");
+    }
+
+    let window = MaintenanceWindow::from_settings(&settings);
+    let Ok(now) = glib::DateTime::now_local() else {
+        return Some("the time is unknown");
+    };
+    if !window.contains(now.day_of_week(), now.hour()) {
+        return Some("outside the maintenance window");
+    }
+
+    // Only update once each time the window comes around
+    let last_run = history::load().into_iter().rev().find(|run| run.unattended);
+    if last_run.is_some_and(|run| now.to_unix() - run.started < window.duration_secs()) {
+        return Some("already updated in this maintenance window");
+    }
+
+    // Leave the user in charge while the window is open
+    if app.active_window().is_some() {
+        return Some("the window is open");
+    }
+
+    if plugins().is_empty() {
+        return Some("none of the enabled plugins can update without a password");
+    }
+
+    if !utils::on_ac_power() {
+        return Some("on battery power");
+    }
+
+    let network = gio::NetworkMonitor::default();
+    if !network.is_network_available() {
+        return Some("the network is unavailable");
+    }
+    if network.is_network_metered() {
+        return Some("the network is metered");
+    }
+
+    None
+}
+
+/// The names of the enabled plugins that can update without asking for a password
+fn plugins() -> Vec<String> {
+    let (plugins, _failed) = utils::installed_plugins();
+    let enabled = utils::enabled_plugins();
+    plugins
+        .into_iter()
+        .filter(|metadata| enabled.contains(&metadata.name))
+        .filter(|metadata| metadata.privileges == Privileges::User)
+        .map(|metadata| metadata.name)
+        .collect()
+}
+
+/// Apply the pending updates of every enabled plugin that can update unattended
+fn run(app: &adw::Application, running: Rc<Cell<bool>>) {
+    println!("Updating unattended...");
+    running.set(true);
+
+    let (tx, rx) = flume::unbounded::<EngineEvent>();
+    let tx = history::recorder(tx, true);
+    let names = plugins();
+    thread::spawn(move || {
+        let mut engine = utils::load_engine(&utils::plugin_paths(&names), tx);
+        // A refused run is reported as an event
+        let _ = engine.run(&HashMap::new(), &CancellationToken::new());
+    });
+
+    glib::spawn_future_local(glib::clone!(
+        #[weak]
+        app,
+        async move {
+            let mut run = Run::new(true);
+            while let Ok(event) = rx.recv_async().await {
+                run.handle(&event);
+                match event {
+                    EngineEvent::StateChanged(EngineState::Finished(_)) => finished(&app, &run),
+                    EngineEvent::Refused(error) => {
+                        eprintln!("Can't update unattended: {}", error);
+                    }
+                    _ => {}
+                }
+            }
+            running.set(false);
+        }
+    ));
+}
+
+/// Say how the run went
+fn finished(app: &adw::Application, run: &Run) {
+    let notification = gio::Notification::new("Updates Installed");
+    notification.set_body(Some(&run.summary()));
+    notification.set_icon(&gio::ThemedIcon::new("software-update-available-symbolic"));
+    app.send_notification(Some(NOTIFICATION_ID), &notification);
+}
+
+/// MaintenanceWindow is when unattended updates may run, i.e. Sundays from 02:00 to 04:00
+struct MaintenanceWindow {
+    /// The day of the week it starts on, 1 for Monday to 7 for Sunday, or 0 for every day
+    day: i32,
+
+    /// The hour it starts at, from 0 to 23
+    start: i32,
+
+    /// The hour it ends at, which is the next day if it's not after `start`
+    end: i32,
+}
+
+impl MaintenanceWindow {
+    fn from_settings(settings: &gio::Settings) -> Self {
+        Self {
+            day: settings.int("maintenance-day").clamp(0, 7),
+            start: settings.int("maintenance-start").clamp(0, 23),
+            end: settings.int("maintenance-end").clamp(0, 23),
+        }
+    }
+
+    /// How many hours the window lasts
+    fn hours(&self) -> i32 {
+        match (self.end - self.start).rem_euclid(24) {
+            0 => 24,
+            hours => hours,
+        }
+    }
+
+    fn duration_secs(&self) -> i64 {
+        i64::from(self.hours()) * 60 * 60
+    }
+
+    /// Determine if the `hour` of the `day` of the week, 1 for Monday to 7 for Sunday, is
+    /// inside the window
+    fn contains(&self, day: i32, hour: i32) -> bool {
+        // The hours since the window last opened, whether or not it's open
+        let since_start = (hour - self.start).rem_euclid(24);
+
+        // The window that's open may have started the day before
+        let started_yesterday = hour < self.start;
+        let start_day = if started_yesterday {
+            (day + 5) % 7 + 1
+        } else {
+            day
+        };
+
+        since_start < self.hours() && (self.day == 0 || self.day == start_day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONDAY: i32 = 1;
+    const FRIDAY: i32 = 5;
+    const SATURDAY: i32 = 6;
+    const SUNDAY: i32 = 7;
+
+    #[test]
+    fn a_window_on_one_day() {
+        // Sundays from 02:00 to 04:00
+        let window = MaintenanceWindow {
+            day: 7,
+            start: 2,
+            end: 4,
+        };
+        assert_eq!(window.hours(), 2);
+
+        assert!(window.contains(SUNDAY, 2));
+        assert!(window.contains(SUNDAY, 3));
+        assert!(!window.contains(SUNDAY, 1));
+        assert!(!window.contains(SUNDAY, 4));
+
+        // The same time on other days
+        assert!(!window.contains(MONDAY, 3));
+        assert!(!window.contains(SATURDAY, 3));
+    }
+
+    #[test]
+    fn a_window_overnight() {
+        // Saturdays from 22:00 to Sundays at 02:00
+        let window = MaintenanceWindow {
+            day: 6,
+            start: 22,
+            end: 2,
+        };
+        assert_eq!(window.hours(), 4);
+
+        assert!(window.contains(SATURDAY, 22));
+        assert!(window.contains(SATURDAY, 23));
+        assert!(window.contains(SUNDAY, 0));
+        assert!(window.contains(SUNDAY, 1));
+        assert!(!window.contains(SUNDAY, 2));
+        assert!(!window.contains(SATURDAY, 21));
+
+        // Sunday night, and the night before the window
+        assert!(!window.contains(SUNDAY, 23));
+        assert!(!window.contains(SATURDAY, 1));
+        assert!(!window.contains(FRIDAY, 23));
+    }
+
+    #[test]
+    fn a_window_every_day() {
+        let window = MaintenanceWindow {
+            day: 0,
+            start: 3,
+            end: 5,
+        };
+        for day in MONDAY..=SUNDAY {
+            assert!(window.contains(day, 3));
+            assert!(window.contains(day, 4));
+            assert!(!window.contains(day, 5));
+            assert!(!window.contains(day, 2));
+        }
+
+        // A window that ends when it starts lasts all day
+        let window = MaintenanceWindow {
+            day: 0,
+            start: 3,
+            end: 3,
+        };
+        assert_eq!(window.hours(), 24);
+        assert!(window.contains(MONDAY, 2));
+        assert!(window.contains(MONDAY, 3));
+        assert!(window.contains(MONDAY, 0));
+    }
+}
@@ -1,5 +1,16 @@
+use crate::config;
+
+use gio::prelude::*;
+
 use inline_xml::xml;
+use renovatio::engine::{EngineEvent, UpdateEngine, find_conflicts};
+use renovatio::loader::{self, LoadError};
+use renovatio::{PluginMetadata, order};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::process::{Command, Stdio};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
 
 static PLUGIN_DIRS: &[&str] = &[
     "/usr/lib/renovatio/plugins",
@@ -17,23 +28,230 @@ pub fn find_plugins() -> Vec<String> {
             path = shellexpand::tilde(&path).to_string();
         }
 
-        // Scan the files in each directory for .so files
-        if std::fs::exists(&path).unwrap_or(false) {
-            for entry in std::fs::read_dir(&path).unwrap() {
-                let entry = entry.unwrap();
-                if entry.file_type().unwrap().is_file()
-                    && entry.file_name().to_str().unwrap().ends_with(".so")
-                {
-                    let plugin = format!("{}/{}", path, entry.file_name().to_str().unwrap());
+        // Scan the files in each directory for .so files and executables
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Some(file_name) = entry.file_name().to_str().map(|name| name.to_string()) else {
+                continue;
+            };
+            if entry.file_type().is_ok_and(|file_type| file_type.is_file())
+                && (file_name.ends_with(".so") || is_executable(&entry))
+            {
+                let plugin = format!("{}/{}", path, file_name);
 
-                    plugins.push(plugin);
-                }
+                plugins.push(plugin);
             }
         }
     }
     plugins
 }
 
+/// The installed plugins and the plugins that failed to load, from the last scan
+type Installed = (Vec<PluginMetadata>, Vec<(String, LoadError)>);
+
+/// The last scan of the plugin directories, shared by everything that looks plugins up by name
+static INSTALLED: Mutex<Option<Installed>> = Mutex::new(None);
+
+/// Load the metadata of every installed plugin, in run order, and the reason any plugin failed to load.
+/// This scans the plugin directories and loads every plugin, see `installed_plugins` to only
+/// do that once.
+///
+/// Plugins are identified by name, so a plugin in a later directory, i.e. the user's, replaces
+/// the one with the same name in a system directory.
+pub fn load_metadata() -> (Vec<PluginMetadata>, Vec<(String, LoadError)>) {
+    let mut plugins: Vec<PluginMetadata> = Vec::new();
+    let mut failed: Vec<(String, LoadError)> = Vec::new();
+    let mut loaded_plugins = Vec::new();
+
+    for plugin_path in find_plugins() {
+        match loader::load_plugin(&plugin_path) {
+            Ok(loaded) => {
+                let mut metadata = PluginMetadata::new(&*loaded);
+                metadata.path = Some(plugin_path);
+                match plugins.iter().position(|other| other.name == metadata.name) {
+                    Some(index) => {
+                        plugins[index] = metadata;
+                        loaded_plugins[index] = loaded;
+                    }
+                    None => {
+                        plugins.push(metadata);
+                        loaded_plugins.push(loaded);
+                    }
+                }
+            }
+            Err(error) => {
+                eprintln!("Failed to load plugin {}: {}", plugin_path, error);
+                failed.push((plugin_path, error));
+            }
+        }
+    }
+
+    // Record which of the installed plugins conflict with each other
+    for (plugin, other) in find_conflicts(&loaded_plugins) {
+        for metadata in plugins.iter_mut() {
+            if metadata.name == plugin {
+                metadata.conflicts.push(other.clone());
+            } else if metadata.name == other {
+                metadata.conflicts.push(plugin.clone());
+            }
+        }
+    }
+
+    (sort_metadata(plugins, &plugin_order()), failed)
+}
+
+/// The metadata of every installed plugin, and why any failed to load, scanning the plugin
+/// directories the first time they're needed
+pub fn installed_plugins() -> Installed {
+    let mut installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+    installed.get_or_insert_with(load_metadata).clone()
+}
+
+/// Scan the plugin directories again, i.e. to find a plugin installed since the last scan
+pub fn rescan_plugins() -> Installed {
+    let installed = load_metadata();
+    *INSTALLED.lock().unwrap_or_else(PoisonError::into_inner) = Some(installed.clone());
+    installed
+}
+
+/// Get the names of the plugins enabled in the preferences
+pub fn enabled_plugins() -> Vec<String> {
+    let settings = gio::Settings::new(config::APP_ID);
+    settings.get::<Vec<String>>("enabled-plugins")
+}
+
+/// Get the paths of the installed plugins named `names`, in the order given, leaving out
+/// any that aren't installed anymore
+pub fn plugin_paths(names: &[String]) -> Vec<String> {
+    let (mut plugins, _failed) = installed_plugins();
+
+    // Look for a plugin that isn't known yet, in case it was installed since the last scan
+    if names
+        .iter()
+        .any(|name| !plugins.iter().any(|metadata| &metadata.name == name))
+    {
+        (plugins, _) = rescan_plugins();
+    }
+
+    names
+        .iter()
+        .filter_map(|name| {
+            let path = plugins
+                .iter()
+                .find(|metadata| &metadata.name == name)
+                .and_then(|metadata| metadata.path.clone());
+            if path.is_none() {
+                eprintln!("Plugin not found: {}", name);
+            }
+            path
+        })
+        .collect()
+}
+
+/// Move the enabled plugins from the old `plugins` key, which held their paths, to
+/// `enabled-plugins`, which holds their names
+pub fn migrate_enabled_plugins() {
+    let settings = gio::Settings::new(config::APP_ID);
+    let paths = settings.get::<Vec<String>>("plugins");
+    if paths.is_empty() {
+        return;
+    }
+
+    let (plugins, _failed) = installed_plugins();
+    let installed: Vec<(&str, &str)> = plugins
+        .iter()
+        .filter_map(|metadata| Some((metadata.name.as_str(), metadata.path.as_deref()?)))
+        .collect();
+
+    let mut names = settings.get::<Vec<String>>("enabled-plugins");
+    for name in migrated_names(&paths, &installed) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    let _ = settings.set("enabled-plugins", &names);
+    settings.reset("plugins");
+    gio::Settings::sync();
+}
+
+/// The names of the plugins enabled by their `paths`, given the name and path of each
+/// `installed` plugin.
+///
+/// A plugin that moved to another directory is still found by its file name. One that
+/// doesn't load, i.e. because it was built for an older version of renovatio, is named
+/// after its file, so it's still enabled once it's rebuilt.
+fn migrated_names(paths: &[String], installed: &[(&str, &str)]) -> Vec<String> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let file_name = Path::new(path).file_name();
+            let name = installed
+                .iter()
+                .find(|(_, other)| other == path)
+                .or_else(|| {
+                    installed
+                        .iter()
+                        .find(|(_, other)| Path::new(other).file_name() == file_name)
+                })
+                .map(|(name, _)| name.to_string());
+            name.or_else(|| file_plugin_name(path))
+        })
+        .collect()
+}
+
+/// The name a plugin at `path` has if it's named after its crate, i.e. "rpm-ostree" for
+/// `librpm_ostree.so`
+fn file_plugin_name(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let stem = path.file_stem()?.to_str()?;
+    let name = match path.extension() {
+        Some(extension) if extension == "so" => stem.strip_prefix("lib").unwrap_or(stem),
+        _ => path.file_name()?.to_str()?,
+    };
+    Some(name.replace('_', "-"))
+}
+
+/// Sort plugins into the order they run in, letting `user_order` override their declared order
+pub fn sort_metadata(plugins: Vec<PluginMetadata>, user_order: &[String]) -> Vec<PluginMetadata> {
+    let run_orders: Vec<_> = plugins
+        .iter()
+        .map(|metadata| (metadata.name.as_str(), &metadata.run_order))
+        .collect();
+    let sorted = order::sort(&run_orders, user_order);
+
+    sorted
+        .into_iter()
+        .map(|index| plugins[index].clone())
+        .collect()
+}
+
+/// Get the plugin names the user arranged in the preferences, in order
+pub fn plugin_order() -> Vec<String> {
+    let settings = gio::Settings::new(config::APP_ID);
+    settings.get::<Vec<String>>("plugin-order")
+}
+
+/// Load the plugins at `paths` into an engine, in the order they should run and
+/// limited to the number of plugins the user allows to update at once
+pub fn load_engine(paths: &[String], events: flume::Sender<EngineEvent>) -> UpdateEngine {
+    let settings = gio::Settings::new(config::APP_ID);
+    let mut engine = UpdateEngine::load(paths, events);
+    engine.sort(&plugin_order());
+    engine.set_max_parallel(settings.int("max-parallel").max(1) as usize);
+    engine
+}
+
+/// Check if a directory entry is an executable, i.e. an out-of-process plugin
+fn is_executable(entry: &std::fs::DirEntry) -> bool {
+    entry
+        .metadata()
+        .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
 /// Installs our GSettings schema, if they're not already installed.
 pub fn install_gsettings_schema() {
     let xml = xml! {
@@ -46,8 +264,59 @@ pub fn install_gsettings_schema() {
 
             <key name="plugins" type="as">
               <default>{"[]"}</default>
-              <summary></summary>
-              <description></description>
+              <summary>Deprecated: the paths of the enabled plugins</summary>
+              <description>Replaced by enabled-plugins, and migrated to it at startup</description>
+            </key>
+
+            <key name="enabled-plugins" type="as">
+              <default>{"[]"}</default>
+              <summary>The enabled plugins</summary>
+              <description>Plugin names, resolved to the plugin installed in the user or system plugin directories</description>
+            </key>
+
+            <key name="plugin-order" type="as">
+              <default>{"[]"}</default>
+              <summary>The order plugins run in</summary>
+              <description>Plugin names, overriding the order plugins declare</description>
+            </key>
+
+            <key name="max-parallel" type="i">
+              <default>2</default>
+              <summary>The number of plugins that can update at once</summary>
+              <description>Plugins that update the same things never run at the same time</description>
+            </key>
+
+            <key name="check-interval" type="i">
+              <default>6</default>
+              <summary>The number of hours between checks for updates in the background</summary>
+              <description>Only used while renovatio runs with --background</description>
+            </key>
+
+            <key name="unattended-updates" type="b">
+              <default>false</default>
+              <summary>Apply updates unattended during the maintenance window</summary>
+              <description>Only on AC power and unmetered connections, while renovatio runs with --background</description>
+            </key>
+
+            <key name="maintenance-day" type="i">
+              <default>7</default>
+              <summary>The day the maintenance window starts on</summary>
+              <description>1 for Monday to 7 for Sunday, or 0 for every day</description>
+            </key>
+
+            <key name="maintenance-start" type="i">
+              <default>2</default>
+              <summary>The hour the maintenance window starts at</summary>
+            </key>
+
+            <key name="maintenance-end" type="i">
+              <default>4</default>
+              <summary>The hour the maintenance window ends at</summary>
+            </key>
+
+            <key name="unattended-seen" type="x">
+              <default>0</default>
+              <summary>When the last unattended run the user looked at started</summary>
             </key>
 
             </schema>
@@ -66,7 +335,8 @@ pub fn install_gsettings_schema() {
         glib_schemas_dir, "com.github.AdamIsrael.Renovatio.gschema.xml"
     );
 
-    if !std::fs::exists(&xml_path).unwrap_or(false) {
+    // Reinstall the schema when keys are added
+    if std::fs::read_to_string(&xml_path).ok() != Some(xml.to_string()) {
         println!("Installing schema...");
         std::fs::write(&xml_path, xml.to_string()).unwrap();
 
@@ -79,6 +349,40 @@ pub fn install_gsettings_schema() {
     }
 }
 
+/// Format a duration for display, i.e. "1m 23s"
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 60 {
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Determine if the computer is running on AC power. Computers without a battery always are.
+pub fn on_ac_power() -> bool {
+    let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else {
+        return true;
+    };
+
+    let read = |supply: &Path, name: &str| {
+        std::fs::read_to_string(supply.join(name))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    let mut has_battery = false;
+    for supply in supplies.flatten() {
+        let supply = supply.path();
+        match read(&supply, "type").as_str() {
+            "Mains" if read(&supply, "online") == "1" => return true,
+            "Battery" => has_battery = true,
+            _ => {}
+        }
+    }
+    !has_battery
+}
+
 /// Checks rpm-ostree status for pending updates.
 pub fn check_reboot_needed() -> bool {
     let cmd = "rpm-ostree status --pending-exit-77";
@@ -100,3 +404,49 @@ pub fn reboot_system() -> bool {
         .expect("Failed to reboot");
     rc.code() == Some(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INSTALLED: &[(&str, &str)] = &[
+        ("bootc", "/usr/lib/renovatio/plugins/libbootc.so"),
+        (
+            "flatpak",
+            "/home/user/.local/lib/renovatio/plugins/libflatpak.so",
+        ),
+        ("my-tool", "/usr/local/lib/renovatio/plugins/my-tool-plugin"),
+    ];
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn migrates_installed_plugins_by_path() {
+        let paths = paths(&[
+            "/usr/lib/renovatio/plugins/libbootc.so",
+            "/usr/local/lib/renovatio/plugins/my-tool-plugin",
+        ]);
+        assert_eq!(migrated_names(&paths, INSTALLED), ["bootc", "my-tool"]);
+    }
+
+    #[test]
+    fn migrates_plugins_that_moved_by_file_name() {
+        let paths = paths(&["/usr/lib/renovatio/plugins/libflatpak.so"]);
+        assert_eq!(migrated_names(&paths, INSTALLED), ["flatpak"]);
+    }
+
+    #[test]
+    fn migrates_plugins_that_dont_load_by_file_name() {
+        let paths = paths(&[
+            "/usr/lib/renovatio/plugins/librpm_ostree.so",
+            "/usr/lib/renovatio/plugins/libbrew.so",
+            "/usr/local/lib/renovatio/plugins/other-tool",
+        ]);
+        assert_eq!(
+            migrated_names(&paths, &[]),
+            ["rpm-ostree", "brew", "other-tool"]
+        );
+    }
+}